@@ -0,0 +1,167 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use crate::{utils, Args, Generation};
+
+/// A line of the audit log.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub args: &'a Args,
+    pub files: usize,
+    pub tokens: Option<u32>,
+    pub outcome: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Append the outcome of generating a project to the audit log at `audit_path`, redacted with
+/// `redactor`. The file is locked while writing so concurrent runs don't interleave their lines.
+pub fn append(
+    audit_path: &str,
+    args: &Args,
+    res: &anyhow::Result<Generation>,
+    redactor: &utils::Redactor,
+) -> anyhow::Result<()> {
+    let entry = AuditEntry {
+        timestamp: Utc::now(),
+        args,
+        files: res.as_ref().map(|g| g.files).unwrap_or(0),
+        tokens: res.as_ref().ok().and_then(|g| g.tokens),
+        outcome: if res.is_ok() { "success" } else { "failure" },
+        error: res.as_ref().err().map(|e| e.to_string()),
+    };
+    let mut line = serde_json::to_string(&redactor.redact_json(&serde_json::to_value(&entry)?))?;
+    line.push('\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path)
+        .map_err(|e| anyhow::anyhow!("Failed to open audit log `{}`: {}", audit_path, e))?;
+    file.lock()?;
+    let res = file.write_all(line.as_bytes());
+    file.unlock()?;
+    res.map_err(|e| anyhow::anyhow!("Failed to write audit log `{}`: {}", audit_path, e))
+}
+
+/// The fields of an audit log line that the history shows.
+#[derive(Debug, Deserialize)]
+struct HistoryEntry {
+    timestamp: DateTime<Utc>,
+    args: HistoryArgs,
+    files: usize,
+    tokens: Option<u32>,
+    outcome: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryArgs {
+    name: String,
+    language: String,
+    model: String,
+}
+
+/// Which entries of the audit log the history shows.
+#[derive(Debug, Default)]
+pub struct HistoryFilter<'a> {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub language: Option<&'a str>,
+    pub model: Option<&'a str>,
+}
+
+impl HistoryFilter<'_> {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+            && self
+                .language
+                .is_none_or(|language| entry.args.language.eq_ignore_ascii_case(language))
+            && self.model.is_none_or(|model| entry.args.model == model)
+    }
+}
+
+/// Print the entries of the audit log at `audit_path` that match `filter`.
+pub fn print_history(audit_path: &str, filter: &HistoryFilter) -> anyhow::Result<()> {
+    let contents = fs::read_to_string(audit_path)
+        .map_err(|e| anyhow::anyhow!("Failed to read audit log `{}`: {}", audit_path, e))?;
+    let mut shown = 0;
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: HistoryEntry = serde_json::from_str(line).map_err(|e| {
+            anyhow::anyhow!("Invalid entry on line {} of `{}`: {}", i + 1, audit_path, e)
+        })?;
+        if !filter.matches(&entry) {
+            continue;
+        }
+        println!(
+            "{} {} `{}` ({}, {}): {} file(s), {} tokens",
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            entry.outcome,
+            entry.args.name,
+            entry.args.language,
+            entry.args.model,
+            entry.files,
+            entry
+                .tokens
+                .map_or_else(|| "unknown".to_string(), |tokens| tokens.to_string())
+        );
+        shown += 1;
+    }
+    if shown == 0 {
+        println!("No matching entries in `{}`", audit_path);
+    }
+    Ok(())
+}
+
+// A point in time: an RFC 3339 timestamp, a date at `day_offset` days after its midnight,
+// or a duration ago like `7d`, `12h`, `30m` or `2w`.
+fn parse_time(time: &str, day_offset: i64) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(time) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        let midnight = date
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight exists")
+            .and_utc();
+        return Duration::try_days(day_offset)
+            .and_then(|offset| midnight.checked_add_signed(offset))
+            .ok_or_else(|| format!("`{}` is out of range", time));
+    }
+    let (amount, unit) = time.split_at(time.len() - time.chars().last().map_or(0, char::len_utf8));
+    let amount: i64 = amount.parse().map_err(|_| {
+        format!(
+            "expected a date, a timestamp or a duration like 7d, got `{}`",
+            time
+        )
+    })?;
+    let ago = match unit {
+        "w" => Duration::try_weeks(amount),
+        "d" => Duration::try_days(amount),
+        "h" => Duration::try_hours(amount),
+        "m" => Duration::try_minutes(amount),
+        _ => {
+            return Err(format!(
+                "unknown duration unit in `{}`, expected w, d, h or m",
+                time
+            ))
+        }
+    };
+    ago.and_then(|ago| Utc::now().checked_sub_signed(ago))
+        .ok_or_else(|| format!("`{}` is out of range", time))
+}
+
+pub fn parse_since(time: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(time, 0)
+}
+
+// A date includes the whole day.
+pub fn parse_until(time: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(time, 1)
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::{utils, Args, Generation};
+
+/// A project to generate in a batch run, unset fields fall back to the cmdline arguments.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchEntry {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub language: Option<String>,
+}
+
+impl BatchEntry {
+    pub fn apply(&self, args: &mut Args) {
+        if let Some(name) = &self.name {
+            args.name = name.clone();
+        }
+        if let Some(description) = &self.description {
+            args.description = description.clone();
+        }
+        if let Some(language) = &self.language {
+            args.language = language.clone();
+        }
+    }
+}
+
+/// Read the entries of a JSON lines batch file, blank lines are ignored.
+pub fn read_entries(batch_path: &str) -> anyhow::Result<Vec<BatchEntry>> {
+    let contents = fs::read_to_string(batch_path)
+        .map_err(|e| anyhow::anyhow!("Failed to read batch file `{}`: {}", batch_path, e))?;
+
+    let entries = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                anyhow::anyhow!("Invalid entry on line {} of `{}`: {}", i + 1, batch_path, e)
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if entries.is_empty() {
+        anyhow::bail!("Batch file `{}` doesn't contain any entries", batch_path);
+    }
+    Ok(entries)
+}
+
+/// Whether generating a batch entry succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryStatus {
+    Done,
+    Failed,
+}
+
+/// The outcome of generating a batch entry.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EntryState {
+    pub name: String,
+    pub status: EntryStatus,
+    pub files: usize,
+    pub tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Progress of a batch run, keyed by the index of the entry in the batch file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BatchState {
+    entries: BTreeMap<usize, EntryState>,
+}
+
+impl BatchState {
+    /// Where the progress of the batch file at `batch_path` is kept.
+    pub fn path(batch_path: &str) -> String {
+        format!("{}.state.json", batch_path)
+    }
+
+    /// Read the state at `state_path`, a missing file means nothing was generated yet.
+    pub fn read(state_path: &str) -> anyhow::Result<Self> {
+        if !Path::new(state_path).exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(state_path)?;
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid batch state `{}`: {}", state_path, e))
+    }
+
+    /// Whether the entry at `index` was already generated. The name has to match as well, so
+    /// an edited batch file doesn't skip the wrong entries.
+    pub fn is_done(&self, index: usize, name: &str) -> bool {
+        self.entries
+            .get(&index)
+            .is_some_and(|entry| entry.status == EntryStatus::Done && entry.name == name)
+    }
+
+    /// Record the outcome of generating an entry. Runs that wrote nothing, like previews or
+    /// --prompt-only, are left out so that resuming still generates the entry.
+    pub fn record(&mut self, index: usize, name: &str, res: &anyhow::Result<Generation>) {
+        if res.as_ref().is_ok_and(|g| g.files == 0) {
+            return;
+        }
+        let entry = EntryState {
+            name: name.to_string(),
+            status: if res.is_ok() {
+                EntryStatus::Done
+            } else {
+                EntryStatus::Failed
+            },
+            files: res.as_ref().map(|g| g.files).unwrap_or(0),
+            tokens: res.as_ref().ok().and_then(|g| g.tokens),
+            error: res.as_ref().err().map(|e| e.to_string()),
+        };
+        self.entries.insert(index, entry);
+    }
+
+    /// Write the state to `state_path` as JSON indented with `indent` spaces, replacing the
+    /// previous state at once.
+    pub fn write(&self, state_path: &str, indent: usize) -> anyhow::Result<()> {
+        let tmp_path = format!("{}.{}.tmp", state_path, utils::unique_suffix());
+        fs::write(&tmp_path, utils::to_json(self, indent)?)?;
+        fs::rename(&tmp_path, state_path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use crate::utils::{self, OutputJson, SourceFile};
+use similar::TextDiff;
+use std::fs;
+use std::path::Path;
+
+/// How a generated file relates to the file already in the project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileStatus {
+    New,
+    Modified,
+    Unchanged,
+}
+
+impl FileStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            FileStatus::New => "new",
+            FileStatus::Modified => "modified",
+            FileStatus::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// The status of `file` along with the contents of the existing file.
+fn file_status(project_path: &str, file: &SourceFile) -> anyhow::Result<(FileStatus, String)> {
+    let path = format!("{}/{}", project_path, file.name);
+    if !Path::new(&path).exists() {
+        return Ok((FileStatus::New, String::new()));
+    }
+    let existing = fs::read_to_string(&path)?;
+    let status = if existing == file.contents {
+        FileStatus::Unchanged
+    } else {
+        FileStatus::Modified
+    };
+    Ok((status, existing))
+}
+
+/// Print the unified diff of `file` against `existing`, limited to `preview_lines` lines after
+/// the header.
+fn print_file_diff(
+    file: &SourceFile,
+    status: FileStatus,
+    existing: &str,
+    preview_lines: Option<usize>,
+) {
+    let old_header = match status {
+        FileStatus::New => "/dev/null".to_string(),
+        _ => format!("a/{}", file.name),
+    };
+    let diff = TextDiff::from_lines(existing, &file.contents);
+    let unified_diff = diff
+        .unified_diff()
+        .header(&old_header, &format!("b/{}", file.name))
+        .to_string();
+    // Always show the `---` and `+++` header lines.
+    let mut lines = unified_diff.splitn(3, '\n');
+    let (old_line, new_line) = (lines.next().unwrap_or(""), lines.next().unwrap_or(""));
+    print!(
+        "{}\n{}\n{}",
+        old_line,
+        new_line,
+        utils::preview(lines.next().unwrap_or(""), preview_lines)
+    );
+}
+
+/// Print a unified diff between the generated project and what's already in `project_path`,
+/// limited to `preview_lines` lines per file after the header.
+pub fn print_project_diff(
+    project_path: &str,
+    output: &OutputJson,
+    preview_lines: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut changed = 0;
+
+    for file in utils::project_files(output) {
+        let (status, existing) = file_status(project_path, &file)?;
+        if status == FileStatus::Unchanged {
+            continue;
+        }
+        print_file_diff(&file, status, &existing, preview_lines);
+        changed += 1;
+    }
+
+    if changed == 0 {
+        println!("No changes compared to `{}`", project_path);
+    } else {
+        println!("{} file(s) would change in `{}`", changed, project_path);
+    }
+
+    Ok(())
+}
+
+/// Print what writing the generated project to `project_path` would do, labeling each file as
+/// new, modified or unchanged. The new and modified files are previewed, as diffs when `diffs`
+/// is set.
+pub fn print_dry_run(
+    project_path: &str,
+    output: &OutputJson,
+    preview_lines: Option<usize>,
+    diffs: bool,
+) -> anyhow::Result<()> {
+    let (mut new, mut modified, mut unchanged) = (0, 0, 0);
+
+    for file in utils::project_files(output) {
+        let (status, existing) = file_status(project_path, &file)?;
+        match status {
+            FileStatus::New => new += 1,
+            FileStatus::Modified => modified += 1,
+            FileStatus::Unchanged => unchanged += 1,
+        }
+        println!(
+            "[{}] {} ({} lines)",
+            status.label(),
+            file.name,
+            file.contents.lines().count()
+        );
+        if status == FileStatus::Unchanged || preview_lines == Some(0) {
+            continue;
+        }
+        if diffs {
+            print_file_diff(&file, status, &existing, preview_lines);
+        } else {
+            print!("{}", utils::preview(&file.contents, preview_lines));
+        }
+    }
+
+    println!(
+        "\nDry run, nothing was written to `{}`: {} new, {} modified and {} unchanged file(s)",
+        project_path, new, modified, unchanged
+    );
+
+    Ok(())
+}
+
+/// A patch, that `git apply` can apply from within the project directory, creating the new
+/// files and changing the modified files of the generated project.
+pub fn project_patch(project_path: &str, output: &OutputJson) -> anyhow::Result<String> {
+    let mut patch = String::new();
+
+    for file in utils::project_files(output) {
+        let (status, existing) = file_status(project_path, &file)?;
+        let old_header = match status {
+            FileStatus::Unchanged => continue,
+            FileStatus::New => "/dev/null".to_string(),
+            FileStatus::Modified => format!("a/{}", file.name),
+        };
+
+        patch.push_str(&format!("diff --git a/{0} b/{0}\n", file.name));
+        if status == FileStatus::New {
+            patch.push_str("new file mode 100644\n");
+        }
+        // Empty new files have no hunks, and no `---` and `+++` header either.
+        patch.push_str(
+            &TextDiff::from_lines(&existing, &file.contents)
+                .unified_diff()
+                .header(&old_header, &format!("b/{}", file.name))
+                .to_string(),
+        );
+    }
+
+    Ok(patch)
+}
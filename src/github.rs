@@ -0,0 +1,115 @@
+use chrono::{Local, TimeZone};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+const API_BASE: &str = "https://api.github.com";
+
+/// A GitHub issue, parsed from its URL.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssueRef {
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+impl IssueRef {
+    pub fn url(&self) -> String {
+        format!(
+            "https://github.com/{}/{}/issues/{}",
+            self.owner, self.repo, self.number
+        )
+    }
+}
+
+pub fn parse_issue_url(url: &str) -> Result<IssueRef, String> {
+    let error = || "expected an issue URL like https://github.com/OWNER/REPO/issues/NUMBER";
+    let url = url.trim_end_matches('/');
+    let path = url
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("github.com/"))
+        .ok_or_else(error)?;
+    match path.split('/').collect::<Vec<_>>()[..] {
+        [owner, repo, "issues", number] if !owner.is_empty() && !repo.is_empty() => Ok(IssueRef {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            number: number.parse().map_err(|_| error())?,
+        }),
+        _ => Err(error().to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Issue {
+    title: String,
+    body: Option<String>,
+}
+
+/// Fetch the title and body of `issue` from the GitHub API, authenticated with `GITHUB_TOKEN`
+/// when it is set, as a project description.
+pub async fn fetch_issue_description(issue: &IssueRef) -> anyhow::Result<String> {
+    let token = env::var("GITHUB_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    let mut req = reqwest::Client::new()
+        .get(format!(
+            "{}/repos/{}/{}/issues/{}",
+            API_BASE, issue.owner, issue.repo, issue.number
+        ))
+        .header(reqwest::header::USER_AGENT, "project-pilot")
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json");
+    if let Some(token) = &token {
+        req = req.bearer_auth(token);
+    }
+    let res = req
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to fetch `{}`: {}", issue.url(), e))?;
+
+    let status = res.status();
+    let rate_limited = res
+        .headers()
+        .get("x-ratelimit-remaining")
+        .is_some_and(|remaining| remaining == "0");
+    if status == StatusCode::TOO_MANY_REQUESTS || (status == StatusCode::FORBIDDEN && rate_limited)
+    {
+        let reset = res
+            .headers()
+            .get("x-ratelimit-reset")
+            .and_then(|reset| reset.to_str().ok()?.parse::<i64>().ok())
+            .and_then(|reset| Local.timestamp_opt(reset, 0).single())
+            .map(|reset| format!(", it resets at {}", reset.format("%H:%M:%S")))
+            .unwrap_or_default();
+        let hint = match token {
+            Some(_) => "",
+            None => ", set GITHUB_TOKEN for a higher limit",
+        };
+        anyhow::bail!("The GitHub API rate limit was exceeded{}{}", reset, hint);
+    }
+    match status {
+        StatusCode::UNAUTHORIZED => {
+            anyhow::bail!("GitHub rejected GITHUB_TOKEN, make sure it is valid and not expired")
+        }
+        // GitHub answers 404 for private repositories it won't show.
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => match token {
+            Some(_) => anyhow::bail!(
+                "Issue `{}` wasn't found, make sure it exists and that GITHUB_TOKEN has access to the repository",
+                issue.url()
+            ),
+            None => anyhow::bail!(
+                "Issue `{}` wasn't found, for a private repository set GITHUB_TOKEN to a token with access to it",
+                issue.url()
+            ),
+        },
+        status if !status.is_success() => {
+            anyhow::bail!("Failed to fetch `{}`: GitHub answered {}", issue.url(), status)
+        }
+        _ => {}
+    }
+
+    let issue: Issue = res.json().await?;
+    Ok(match issue.body.as_deref().map(str::trim) {
+        Some(body) if !body.is_empty() => format!("{}\n\n{}", issue.title, body),
+        _ => issue.title,
+    })
+}
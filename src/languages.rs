@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::utils::{self, CommentStyle};
+
+/// Conventions of a language, unset fields fall back to the built-in defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageConfig {
+    /// Extension of the source files, without the leading dot.
+    pub extension: Option<String>,
+    pub comment_style: Option<CommentStyle>,
+    /// Image the Dockerfile should build from.
+    pub base_image: Option<String>,
+}
+
+/// Language conventions read from a YAML (or JSON) file, keyed by language name.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct LanguageMap(HashMap<String, LanguageConfig>);
+
+impl LanguageMap {
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read language map `{}`: {}", path, e))?;
+        serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid language map `{}`: {}", path, e))
+    }
+
+    /// The conventions configured for `language`, ignoring case.
+    pub fn get(&self, language: &str) -> Option<&LanguageConfig> {
+        self.0
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(language))
+            .map(|(_, config)| config)
+    }
+
+    /// The comment style of a source file, a configured extension takes precedence over the
+    /// built-in ones.
+    pub fn comment_style(&self, file_name: &str) -> Option<CommentStyle> {
+        let extension = Path::new(file_name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        extension
+            .and_then(|extension| {
+                self.0
+                    .values()
+                    .filter(|config| {
+                        config
+                            .extension
+                            .as_deref()
+                            .map(|e| e.trim_start_matches('.'))
+                            == Some(extension.as_str())
+                    })
+                    .find_map(|config| config.comment_style)
+            })
+            .or_else(|| utils::comment_style(file_name))
+    }
+}
+
+pub fn parse_language_map(path: &str) -> Result<LanguageMap, String> {
+    LanguageMap::read(path).map_err(|e| e.to_string())
+}
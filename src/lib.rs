@@ -0,0 +1,45 @@
+//! The project decoded from the model's response and the processors that transform it before
+//! it is written, for tools building on project-pilot.
+//!
+//! ```no_run
+//! use project_pilot::{OutputJson, OutputProcessor, Processors};
+//!
+//! struct Uppercase;
+//!
+//! impl OutputProcessor for Uppercase {
+//!     fn process(&self, output: &mut OutputJson) -> anyhow::Result<()> {
+//!         output.readme = output.readme.to_uppercase();
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut processors = Processors::default();
+//! processors.register(Uppercase);
+//! ```
+
+use std::sync::atomic::AtomicBool;
+
+// Set by --compact-output to keep `info!` from printing anything.
+#[doc(hidden)]
+pub static COMPACT_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+// Print a progress message, like `println!`, unless the output is compact.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::COMPACT_OUTPUT.load(std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Shared with the binary, not a stable API.
+#[doc(hidden)]
+pub mod languages;
+pub mod processors;
+#[doc(hidden)]
+pub mod utils;
+
+pub use processors::{OutputProcessor, Processors};
+pub use utils::{FileOrigin, OutputJson, SourceFile};
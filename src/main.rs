@@ -1,17 +1,56 @@
 use async_openai::{
-    types::{ChatCompletionRequestMessageArgs, CreateChatCompletionRequestArgs, Role},
+    error::OpenAIError,
+    types::{
+        ChatChoice, ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs,
+        CreateChatCompletionRequest, CreateChatCompletionRequestArgs, CreateChatCompletionResponse,
+        Role, Stop,
+    },
     Client,
 };
-use clap::Parser;
-use std::{env, fs};
-use tokio::main;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use futures::StreamExt;
+use serde::Serialize;
+use std::{
+    env, fs,
+    io::{self, IsTerminal, Read, Write},
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+use tokio::{io::AsyncWriteExt, main};
+
+#[macro_use]
+extern crate project_pilot;
+
+use project_pilot::{languages, processors, utils, COMPACT_OUTPUT};
+
+mod audit;
+mod batch;
+mod diff;
+mod github;
+mod metrics;
+mod params;
+mod stream;
+mod summary;
+mod templates;
+mod tools;
+mod validate;
 
-mod utils;
+// Max output tokens when --tokens isn't given, lowered to the limit of the model.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
 
-#[derive(Parser, Debug)]
+// The OpenAI API accepts at most 4 stop sequences.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+#[derive(Parser, Debug, Clone, Serialize)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    // Describe what the program should do, be as specific as possible
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Describe what the program should do, be as specific as possible.
+    // When stdin is not a terminal its contents are used as the description, if --description
+    // is given as well both are joined: the contents of stdin first, then the flag.
     #[arg(
         short,
         long,
@@ -27,95 +66,2190 @@ struct Args {
     #[arg(short, long, default_value = "myapp")]
     name: String,
 
-    // The model to use, see https://platform.openai.com/docs/models for specific models
+    // The model to use, see https://platform.openai.com/docs/models for specific models, or an
+    // alias like fast, smart or cheap
     #[arg(short, long, default_value = "gpt-3.5-turbo")]
     model: String,
 
     // Max allowed tokens
     // See https://beta.openai.com/docs/api-reference/completions/create#max_tokens
     // for more information
-    // Default: 2048
-    #[arg(short, long, default_value = "2048")]
-    tokens: u16,
+    // Default: 4096, or the max output tokens of the model when it is lower
+    #[arg(short, long, default_value_t = DEFAULT_MAX_TOKENS)]
+    tokens: u32,
 
     // The path where to build the project.
     #[arg(short, long, default_value = "./")]
     path: String,
+
+    // Prepend a comment recording the description and model to each source file
+    #[arg(long)]
+    stamp: bool,
+
+    // Write the --stamp comment in this syntax in every source file, instead of detecting it from
+    // the extension: slash (//), hash (#), semicolon (;) or html (<!-- -->)
+    #[arg(long, value_enum, requires = "stamp")]
+    comment_style: Option<utils::CommentStyle>,
+
+    // Stream the response and write source files as soon as they are complete, with --strict
+    // they are only written once the whole project passed the checks
+    #[arg(long)]
+    stream: bool,
+
+    // Write source files in the order the model returned them instead of sorted by name.
+    // Files written while streaming are always written in the order they arrive.
+    #[arg(long)]
+    preserve_order: bool,
+
+    // Only print the model's response verbatim, without decoding it or writing any files
+    #[arg(long)]
+    raw: bool,
+
+    // Treat every warning as an error and exit with a non-zero status.
+    // Warnings found before writing stop the run before any file is written.
+    #[arg(long)]
+    strict: bool,
+
+    // The author to put in generated package manifests like Cargo.toml or package.json
+    #[arg(long)]
+    author: Option<String>,
+
+    // A short description to put in generated package manifests
+    #[arg(long)]
+    summary: Option<String>,
+
+    // Ask the model to call an `emit_project` tool instead of answering with free text JSON.
+    // Only works with models that support tool calling.
+    #[arg(long, conflicts_with = "stream")]
+    tool_mode: bool,
+
+    // Print a diff between the generated files and the existing project instead of writing them
+    #[arg(long, conflicts_with = "stream")]
+    diff: bool,
+
+    // Show which files would be new, modified or unchanged without writing anything, with
+    // --diff the changes are shown as diffs
+    #[arg(long, conflicts_with = "stream")]
+    dry_run: bool,
+
+    // Substitute `${VAR}` references in the description with environment variables
+    #[arg(long)]
+    expand_env: bool,
+
+    // Ask the model to explain its architectural choices and print the explanation
+    #[arg(long)]
+    explain: bool,
+
+    // Append the explanation to the generated README, implies --explain
+    #[arg(long)]
+    explain_to_readme: bool,
+
+    // How many times to ask the model to continue a response truncated by the max tokens limit
+    #[arg(long, default_value = "3")]
+    max_continuations: u32,
+
+    // Save the raw response and the request parameters in the project's `.project-pilot` folder
+    #[arg(long)]
+    keep_raw: bool,
+
+    // Check that generated JSON and YAML files are valid and reformat them consistently
+    #[arg(long)]
+    validate_configs: bool,
+
+    // The kind of project to generate, adds archetype specific instructions to the prompt
+    #[arg(long, value_enum)]
+    preset: Option<utils::Preset>,
+
+    // Generate several projects from a JSON lines file, each line may override the
+    // `name`, `description` and `language` arguments, e.g. {"name": "api", "language": "go"}
+    #[arg(long)]
+    batch: Option<String>,
+
+    // Write metrics about the run in the Prometheus textfile format to this path
+    #[arg(long)]
+    metrics_file: Option<String>,
+
+    // Write a JSON report of the run to this path, also when it fails
+    #[arg(long)]
+    summary_json: Option<String>,
+
+    // Only write files that don't exist yet, useful to fill the gaps of an existing project
+    #[arg(long)]
+    skip_existing_files: bool,
+
+    // Don't write the source files that the .gitignore of an existing project folder ignores
+    #[arg(long)]
+    respect_gitignore: bool,
+
+    // Use the language of an existing file, detected from its shebang or extension
+    #[arg(long, conflicts_with = "language")]
+    detect_from: Option<String>,
+
+    // A sequence where the model stops generating, can be given up to 4 times
+    #[arg(long, value_name = "SEQUENCE")]
+    stop: Vec<String>,
+
+    // Also generate a `.devcontainer/devcontainer.json` based on the Dockerfile
+    #[arg(long)]
+    devcontainer: bool,
+
+    // Copy existing files to this folder before overwriting them, keeping their relative paths
+    #[arg(long)]
+    backup_dir: Option<String>,
+
+    // JSON Pointer to the project inside the response, for gateways that wrap the response
+    // e.g. `/data`. Defaults to the root of the response
+    #[arg(long, default_value = "", value_parser = parse_json_pointer)]
+    json_path: String,
+
+    // How many times to retry a request that failed because of the network: connection errors,
+    // timeouts, interrupted streams and OpenAI server errors. Rate limits are always retried.
+    // A stream interrupted after --stream wrote a file isn't retried
+    #[arg(long, default_value = "2")]
+    max_retries: u32,
+
+    // How many times to send the prompt again when the response isn't valid JSON or doesn't
+    // match the output schema. Every retry is a new, billed, request
+    #[arg(long, default_value = "0")]
+    max_retries_on_parse: u32,
+
+    // What to do with a response that isn't valid JSON: abort, save it to a timestamped file in
+    // the output path, or save it and ask again
+    #[arg(long, value_enum, default_value_t)]
+    on_parse_failure: utils::ParseFailure,
+
+    // Also generate a `docker-compose.yml` with the services the application needs and use it
+    // from the Makefile
+    #[arg(long)]
+    compose: bool,
+
+    // Append a JSON line describing every generated project to this file
+    #[arg(long)]
+    audit_log: Option<String>,
+
+    // Refine the generated project in a conversation with the model before writing it,
+    // files are only written after typing `:save`
+    #[arg(long, conflicts_with_all = ["stream", "raw", "batch"])]
+    interactive_refine: bool,
+
+    // Rename source files starting with `OLD` to start with `NEW` instead, e.g. `src/=lib/`.
+    // Can be given multiple times, the longest matching prefix wins
+    #[arg(long, value_name = "OLD=NEW", value_parser = parse_rename)]
+    rename: Vec<(String, String)>,
+
+    // Send this extra HTTP header with every request, e.g. `X-Tenant-Id: acme`. Can be given
+    // multiple times. Not saved with the other arguments, since values are often credentials
+    #[arg(long, value_name = "KEY: VALUE", value_parser = parse_header)]
+    #[serde(skip)]
+    header: Vec<(String, String)>,
+
+    // Keep the model from wrapping the response in markdown code fences with a `logit_bias`.
+    // The banned token ids depend on the tokenizer of the model, this has no effect for models
+    // with an unknown tokenizer
+    #[arg(long)]
+    no_fences: bool,
+
+    // Reflow the generated README to this many columns, code blocks and lists are preserved
+    #[arg(long, value_name = "COLUMNS")]
+    wrap_width: Option<usize>,
+
+    // Ask the model for a joke and print it first, while streaming as soon as it arrives
+    #[arg(long)]
+    joke_first: bool,
+
+    // Print a curl command equivalent to the request, with the API key redacted, and exit
+    #[arg(long)]
+    dump_request_curl: bool,
+
+    // Generate the project as a workspace member in the packages directory of this monorepo
+    #[arg(long)]
+    monorepo_root: Option<String>,
+
+    // How many leading lines of each file to show in previews, 0 shows only the file names and
+    // a negative number everything
+    #[arg(long, default_value_t = 10, allow_negative_numbers = true)]
+    preview_lines: i64,
+
+    // Include an .editorconfig and a linter config for the language in the project
+    #[arg(long)]
+    tooling: bool,
+
+    // Generate a hello world project in a temporary directory and check that `make build`
+    // succeeds, to verify the setup works
+    #[arg(
+        long,
+        conflicts_with_all = ["batch", "diff", "raw", "dump_request_curl", "interactive_refine", "monorepo_root"]
+    )]
+    self_test: bool,
+
+    // Leave out the Dockerfile and Makefile, generating only the source files and README
+    #[arg(long, conflicts_with_all = ["compose", "self_test"])]
+    no_docker: bool,
+
+    // Read every file back after writing it and fail when it differs from the generated contents
+    #[arg(long)]
+    verify_writes: bool,
+
+    // YAML (or JSON) file mapping language names to their file `extension`, `comment_style`
+    // (slash, hash, semicolon or html) and Dockerfile `base_image`, overriding the built-in
+    // conventions
+    #[arg(long, value_parser = languages::parse_language_map)]
+    language_map: Option<languages::LanguageMap>,
+
+    // Skip the batch entries that were generated by a previous run of the same batch file,
+    // progress is kept next to the batch file in `<batch>.state.json`
+    #[arg(long, requires = "batch")]
+    resume: bool,
+
+    // Also generate a Dockerfile.dev for local development with hot reload and dev dependencies,
+    // run by a `make dev` target
+    #[arg(long, conflicts_with = "no_docker")]
+    dev_dockerfile: bool,
+
+    // Also add this target to the Makefile, e.g. `lint`, `fmt` or `clean`. Can be given multiple
+    // times
+    #[arg(long, value_parser = parse_make_target, conflicts_with = "no_docker")]
+    make_target: Vec<String>,
+
+    // Don't check that the API key is valid before generating
+    #[arg(long)]
+    skip_auth_check: bool,
+
+    // Transliterate non-ASCII characters in the names of the source files to ASCII
+    #[arg(long)]
+    ascii_filenames: bool,
+
+    // Print the system and user messages of the prompt and exit without calling the API
+    #[arg(long)]
+    prompt_only: bool,
+
+    // Write the messages printed by --prompt-only to this file instead
+    #[arg(long, requires = "prompt_only")]
+    prompt_out: Option<String>,
+
+    // Penalize tokens that already appeared, from -2.0 to 2.0, to make the model move on to
+    // new topics
+    #[arg(long, value_parser = parse_penalty, allow_negative_numbers = true)]
+    presence_penalty: Option<f32>,
+
+    // Penalize tokens by how often they already appeared, from -2.0 to 2.0, to make the model
+    // repeat itself less
+    #[arg(long, value_parser = parse_penalty, allow_negative_numbers = true)]
+    frequency_penalty: Option<f32>,
+
+    // Also generate a .dockerignore keeping dependencies and build output out of the image
+    #[arg(long, conflicts_with = "no_docker")]
+    dockerignore: bool,
+
+    // Also add a .gitignore for the language to the project root, from the built-in templates
+    // for common languages and asked from the model for the others
+    #[arg(long)]
+    gitignore: bool,
+
+    // Comma separated fields the response must contain and not leave empty, all of them by
+    // default, only the readme and source_files with --no-docker
+    #[arg(long, value_enum, value_delimiter = ',')]
+    require_fields: Option<Vec<utils::OutputField>>,
+
+    // Also generate a .pre-commit-config.yaml with the usual hooks for the language
+    #[arg(long)]
+    precommit: bool,
+
+    // Directory, relative to the project root, where the post generation commands run, for
+    // projects that keep the buildable code in a subdirectory
+    #[arg(long, value_parser = parse_workdir)]
+    workdir: Option<String>,
+
+    // Print the generated project as a patch to `git apply` from the project directory instead
+    // of writing it, files that already exist are patched
+    #[arg(long, conflicts_with_all = ["stream", "diff", "dry_run"])]
+    as_patch: bool,
+
+    // Write the patch of --as-patch to this file instead
+    #[arg(long, requires = "as_patch")]
+    patch_out: Option<String>,
+
+    // Only sample from the most likely tokens making up this probability mass, from 0.0 to 1.0
+    #[arg(long, value_parser = parse_top_p)]
+    top_p: Option<f32>,
+
+    // Also generate Kubernetes Deployment and Service manifests in a k8s directory
+    #[arg(long, conflicts_with = "no_docker")]
+    k8s: bool,
+
+    // Also generate a minimal Helm chart deploying the image in a chart directory
+    #[arg(long, conflicts_with = "no_docker")]
+    helm: bool,
+
+    // Clean up the source file names, like `./src//main.rs` or `src/../src/main.rs` to
+    // `src/main.rs`, names leaving the project folder are rejected
+    #[arg(long)]
+    normalize_paths: bool,
+
+    // Generate the project from a built-in template instead of asking the model, offline
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["raw", "dump_request_curl", "prompt_only", "stream", "tool_mode", "n", "interactive_refine", "response_command", "estimate_cost"]
+    )]
+    template: Option<templates::Template>,
+
+    // Indent the JSON written by project-pilot with this many spaces, 0 writes it on one line.
+    // The audit log always has one entry per line
+    #[arg(long, default_value_t = 2)]
+    json_indent: usize,
+
+    // Keep generating the other projects of the batch when one fails, and report all the
+    // failures at the end
+    #[arg(long, requires = "batch")]
+    keep_going: bool,
+
+    // Only print the path of each generated project, or the error when generating fails
+    #[arg(
+        long,
+        conflicts_with_all = ["raw", "dump_request_curl", "prompt_only", "as_patch", "diff", "dry_run", "interactive_refine"]
+    )]
+    compact_output: bool,
+
+    // Prepend an SPDX license identifier comment, like MIT, to each source file
+    #[arg(long, conflicts_with = "stream")]
+    spdx_license: Option<String>,
+
+    // Add a manifest listing every generated file with its size to .project-pilot/manifest.json
+    #[arg(long, conflicts_with = "stream")]
+    manifest: bool,
+
+    // TOML file with an `[aliases]` table of short names for --model, on top of the built-in
+    // fast, smart and cheap
+    #[arg(long)]
+    model_aliases: Option<String>,
+
+    // Use the files matching this glob pattern, in sorted order, as the description. When the
+    // description is given as well, or piped through stdin, it follows the files
+    #[arg(long)]
+    spec_glob: Option<String>,
+
+    // Use the title and body of this GitHub issue as the description, read with GITHUB_TOKEN
+    // when set. When the description is given as well, or piped through stdin, it follows the
+    // issue
+    #[arg(long, value_parser = github::parse_issue_url)]
+    from_issue: Option<github::IssueRef>,
+
+    // Also generate an openapi.yaml describing the endpoints, meant for the web-api preset
+    #[arg(long)]
+    openapi: bool,
+
+    // How the project directory is named after --name: plain, or suffixed with a timestamp or
+    // random digits to avoid collisions between runs
+    #[arg(long, value_enum, default_value_t, conflicts_with = "monorepo_root")]
+    name_scheme: utils::NameScheme,
+
+    // Time writing each file and print them from the slowest to the fastest
+    #[arg(long)]
+    profile_writes: bool,
+
+    // Replace this text with *** in the printed and logged prompts and responses, can be
+    // repeated. The API key, and anything looking like an API key, is always redacted
+    #[arg(long)]
+    #[serde(skip)]
+    redact: Vec<String>,
+
+    // Request this many variants of the project at once, from 1 to 10, and choose the one to
+    // write from the valid ones. Truncated variants aren't continued
+    #[arg(
+        long,
+        visible_alias = "count",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..=10),
+        conflicts_with_all = ["stream", "tool_mode", "raw", "dump_request_curl", "interactive_refine", "response_command"]
+    )]
+    n: u8,
+
+    // Choose the variant of --n without asking, for scripts
+    #[arg(long, value_enum)]
+    auto_choose: Option<utils::AutoChoose>,
+
+    // Write the project even when the model returned no source files, Dockerfile or Makefile
+    #[arg(long)]
+    allow_empty_project: bool,
+
+    // Write at most this many source files at the same time
+    #[arg(long, default_value_t = 8, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    write_concurrency: usize,
+
+    // Record the command line and the resolved arguments, with secrets redacted, in
+    // .project-pilot/invocation.txt
+    #[arg(long)]
+    echo_args: bool,
+
+    // JSON file with the sampling parameters: temperature, top_p, presence_penalty,
+    // frequency_penalty, stop and logit_bias. The matching flags override it
+    #[arg(long, value_parser = params::parse_model_params)]
+    model_params_file: Option<params::ModelParams>,
+
+    // Estimate the cost of generating the projects, assuming the full --tokens are used,
+    // without calling the API
+    #[arg(long, conflicts_with_all = ["self_test", "raw", "dump_request_curl", "prompt_only"])]
+    estimate_cost: bool,
+
+    // Leave out the disclaimer about the generated code, and the joke of --joke-first
+    #[arg(long)]
+    no_disclaimer: bool,
+
+    // Get the response from the stdout of this shell command, given the prompt on stdin,
+    // instead of from OpenAI, to replay recorded responses or use another backend
+    #[arg(long, conflicts_with_all = ["stream", "tool_mode", "raw"])]
+    response_command: Option<String>,
+}
+
+#[derive(clap::Subcommand, Debug, Clone, Serialize)]
+enum Command {
+    // Ask the model whether an existing project satisfies its spec and list the gaps
+    ValidateProject {
+        // The folder of the project to validate
+        dir: String,
+
+        // The spec the project should satisfy
+        #[arg(short, long)]
+        description: String,
+    },
+    // Run `make build` in every project folder of a directory, like a batch output, and report
+    // which ones pass
+    ValidateAll {
+        // The folder containing the projects, each one with a Makefile
+        dir: String,
+
+        // Build at most this many projects at the same time
+        #[arg(long, default_value_t = 4, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        concurrency: usize,
+    },
+    // Print the projects generated according to an audit log written with --audit-log
+    History {
+        // The audit log to read
+        audit_log: String,
+
+        // Only show the projects generated since this date, timestamp or duration ago (7d)
+        #[arg(long, value_parser = audit::parse_since)]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+
+        // Only show the projects generated until this date (included), timestamp or duration
+        // ago
+        #[arg(long, value_parser = audit::parse_until)]
+        until: Option<chrono::DateTime<chrono::Utc>>,
+
+        // Only show the projects in this programming language
+        #[arg(long)]
+        language: Option<String>,
+
+        // Only show the projects generated with this model
+        #[arg(long)]
+        model: Option<String>,
+    },
 }
 
 #[main]
 async fn main() -> anyhow::Result<()> {
-    // Check if the API key is set in the environment.
-    if env::var("OPENAI_API_KEY").is_err() {
+    // Parse cmdline arguments.
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches)?;
+
+    if let Some(Command::History {
+        audit_log,
+        since,
+        until,
+        language,
+        model,
+    }) = &args.command
+    {
+        let filter = audit::HistoryFilter {
+            since: *since,
+            until: *until,
+            language: language.as_deref(),
+            model: model.as_deref(),
+        };
+        return audit::print_history(audit_log, &filter);
+    }
+    if let Some(Command::ValidateAll { dir, concurrency }) = &args.command {
+        return validate::validate_all(dir, *concurrency).await;
+    }
+
+    let summary_path = args.summary_json.clone();
+    let json_indent = args.json_indent;
+    let mut run_summary = summary::RunSummary::default();
+    let run_start = Instant::now();
+    let res = run(args, &matches, &mut run_summary).await;
+    if let Some(summary_path) = &summary_path {
+        if let Err(e) = run_summary.write(summary_path, &res, run_start.elapsed(), json_indent) {
+            if res.is_ok() {
+                return Err(e);
+            }
+            eprintln!("{:#}", e);
+        }
+    }
+    res
+}
+
+// Run everything after the arguments are parsed, recording the projects in `run_summary`.
+async fn run(
+    mut args: Args,
+    matches: &ArgMatches,
+    run_summary: &mut summary::RunSummary,
+) -> anyhow::Result<()> {
+    if let Some(template) = args.template {
+        if args.language != template.language() {
+            info!(
+                "Using the language of the `{}` template, {}",
+                template.name(),
+                template.language()
+            );
+            args.language = template.language().to_string();
+        }
+    }
+
+    // Check if the API key is set in the environment, unless OpenAI isn't used.
+    if env::var("OPENAI_API_KEY").is_err()
+        && !args.prompt_only
+        && !args.dump_request_curl
+        && args.response_command.is_none()
+        && !args.estimate_cost
+        && args.template.is_none()
+    {
         eprintln!("OPENAI_API_KEY environment variable not set.\nExample:\nexport OPENAI_API_KEY=<your-api-key>");
+        if args.self_test {
+            anyhow::bail!("The self-test can't run without an API key");
+        }
         return Ok(());
     }
+    COMPACT_OUTPUT.store(args.compact_output, Ordering::Relaxed);
+    let model = utils::resolve_model_alias(&args.model, args.model_aliases.as_deref())?;
+    if model != args.model {
+        info!("Using model `{}` for `{}`", model, args.model);
+        args.model = model;
+    }
+    if let Some(base) = utils::fine_tuned_base(&args.model) {
+        info!(
+            "Using a fine-tuned model, with the limits of its base model `{}`",
+            base
+        );
+    }
+    let mut description_given =
+        matches.value_source("description") == Some(ValueSource::CommandLine);
+    if !io::stdin().is_terminal() {
+        let mut piped = String::new();
+        io::stdin().read_to_string(&mut piped)?;
+        let piped = piped.trim();
+        if !piped.is_empty() {
+            args.description = if description_given {
+                format!("{}\n\n{}", piped, args.description)
+            } else {
+                piped.to_string()
+            };
+            description_given = true;
+        }
+    }
+    if let Some(pattern) = &args.spec_glob {
+        let spec = utils::read_spec_files(pattern)?;
+        args.description = if description_given {
+            format!("{}\n\n{}", spec, args.description)
+        } else {
+            spec
+        };
+        description_given = true;
+    }
+    if let Some(issue) = &args.from_issue {
+        info!("Fetching the description from `{}`", issue.url());
+        let issue = github::fetch_issue_description(issue).await?;
+        args.description = if description_given {
+            format!("{}\n\n{}", issue, args.description)
+        } else {
+            issue
+        };
+    }
+    if let Some(max) = utils::max_output_tokens(&args.model) {
+        if matches.value_source("tokens") != Some(ValueSource::CommandLine) {
+            args.tokens = args.tokens.min(max);
+        } else if args.tokens > max {
+            info!(
+                "Warning: `{}` supports at most {} output tokens, lowering --tokens from {}",
+                args.model, max, args.tokens
+            );
+            args.tokens = max;
+        }
+    }
+    if args.tokens > u32::from(u16::MAX) {
+        info!(
+            "Warning: at most {} max tokens can be requested, lowering --tokens from {}",
+            u16::MAX,
+            args.tokens
+        );
+        args.tokens = u32::from(u16::MAX);
+    }
+    if args.no_docker
+        && required_fields(&args).iter().any(|field| {
+            matches!(
+                field,
+                utils::OutputField::Dockerfile | utils::OutputField::Makefile
+            )
+        })
+    {
+        anyhow::bail!("The dockerfile and makefile can't be required with --no-docker");
+    }
+    if args.openapi && !matches!(args.preset, Some(utils::Preset::WebApi)) {
+        info!("Warning: --openapi is meant for web APIs, consider using it with --preset web-api");
+    }
+    let params = args.model_params_file.clone().unwrap_or_default();
+    if params.temperature.is_some() && (args.top_p.is_some() || params.top_p.is_some()) {
+        info!("Warning: both temperature and top_p are set, OpenAI recommends changing only one of them");
+    }
+    if args.stop.len() > MAX_STOP_SEQUENCES {
+        anyhow::bail!(
+            "At most {} stop sequences are allowed, got {}",
+            MAX_STOP_SEQUENCES,
+            args.stop.len()
+        );
+    }
+    for (i, (old, new)) in args.rename.iter().enumerate() {
+        if let Some((_, other)) = args.rename[..i].iter().find(|(o, n)| o == old && n != new) {
+            anyhow::bail!(
+                "Conflicting rename rules for `{}`: `{}` and `{}`",
+                old,
+                other,
+                new
+            );
+        }
+    }
+    if let Some(detect_path) = &args.detect_from {
+        args.language = utils::detect_language(detect_path)?;
+        info!(
+            "Detected language `{}` from `{}`",
+            args.language, detect_path
+        );
+    }
+    let http_client = http_client(&args.header)?;
+    let client = Client::new().with_http_client(http_client.clone());
+    if !args.skip_auth_check
+        && !args.dump_request_curl
+        && !args.prompt_only
+        && args.response_command.is_none()
+        && !args.estimate_cost
+        && args.template.is_none()
+    {
+        check_api_key(&http_client, &client).await?;
+    }
+
+    if let Some(Command::ValidateProject { dir, description }) = &args.command {
+        return validate::validate_project(&args, &client, dir, description).await;
+    }
 
-    // Parse cmdline arguments and build prompt.
-    let args = Args::parse();
-    let prompt = utils::generate_prompt(&args.name, &args.description, &args.language);
+    if args.self_test {
+        return self_test(&args, &http_client, &client).await;
+    }
+
+    let entries = match &args.batch {
+        Some(batch_path) => batch::read_entries(batch_path)?,
+        None => vec![batch::BatchEntry::default()],
+    };
+    let mut metrics = metrics::Metrics::default();
+    let (mut total_cost, mut unknown_costs) = (0.0, 0);
+    let mut failures = Vec::new();
+    let state_path = args.batch.as_deref().map(batch::BatchState::path);
+    let mut state = match &state_path {
+        Some(state_path) if args.resume => batch::BatchState::read(state_path)?,
+        _ => batch::BatchState::default(),
+    };
+
+    for (i, entry) in entries.iter().enumerate() {
+        let mut entry_args = args.clone();
+        entry.apply(&mut entry_args);
+        if state.is_done(i, &entry_args.name) {
+            info!(
+                "Skipping project {}/{}: `{}`, it was already generated",
+                i + 1,
+                entries.len(),
+                entry_args.name
+            );
+            continue;
+        }
+        if entry_args.expand_env {
+            entry_args.description = utils::expand_env(&entry_args.description)?;
+        }
+        if args.estimate_cost {
+            match estimate_cost(&entry_args)? {
+                Some(cost) => total_cost += cost,
+                None => unknown_costs += 1,
+            }
+            continue;
+        }
+        if args.batch.is_some() {
+            info!(
+                "Generating project {}/{}: `{}` 🤖\n",
+                i + 1,
+                entries.len(),
+                entry_args.name
+            );
+        }
 
-    println!("Sending prompt: {}", prompt);
+        let mut report = summary::ProjectSummary {
+            name: entry_args.name.clone(),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let res = generate(&entry_args, &http_client, &client, &mut report).await;
+        metrics.record(res.as_ref().ok(), start.elapsed());
+        // The writes were already taken when profiling them.
+        let writes = match &res {
+            Ok(generation) if args.profile_writes => generation.writes.clone(),
+            _ => utils::take_write_profile(),
+        };
+        run_summary.record(report, &res, start.elapsed(), &writes);
+        if let Some(audit_path) = &args.audit_log {
+            let redactor = utils::Redactor::new(&entry_args.redact);
+            audit::append(audit_path, &entry_args, &res, &redactor)?;
+        }
+        if let Some(state_path) = &state_path {
+            state.record(i, &entry_args.name, &res);
+            state.write(state_path, args.json_indent)?;
+        }
+        if let Err(e) = res {
+            if args.keep_going {
+                info!("Failed to generate `{}`: {:#}\n", entry_args.name, e);
+                failures.push((entry_args.name, e));
+                continue;
+            }
+            if let Some(metrics_path) = &args.metrics_file {
+                metrics.write(metrics_path)?;
+            }
+            return Err(e);
+        }
+    }
+
+    if args.estimate_cost {
+        if args.batch.is_some() {
+            info!(
+                "\nEstimated cost of the batch: at most ${:.4}{}",
+                total_cost,
+                match unknown_costs {
+                    0 => String::new(),
+                    unknown => format!(", without the {} project(s) of unknown price", unknown),
+                }
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(metrics_path) = &args.metrics_file {
+        metrics.write(metrics_path)?;
+    }
+
+    if !failures.is_empty() {
+        eprintln!("\nFailed projects:");
+        for (name, e) in &failures {
+            eprintln!("- `{}`: {:#}", name, e);
+        }
+        anyhow::bail!(
+            "{} of {} project(s) failed to generate",
+            failures.len(),
+            entries.len()
+        );
+    }
+
+    Ok(())
+}
+
+// The outcome of generating a single project.
+struct Generation {
+    tokens: Option<u32>,
+    // The number of files written to the project folder.
+    files: usize,
+    // The files written with --profile-writes, the slowest first.
+    writes: Vec<utils::FileWrite>,
+}
+
+// Generate a single project as described by the arguments, filling in `report` as it goes.
+async fn generate(
+    args: &Args,
+    http_client: &reqwest::Client,
+    client: &Client,
+    report: &mut summary::ProjectSummary,
+) -> anyhow::Result<Generation> {
+    // Build the prompt.
+    let quiet = args.raw || args.dump_request_curl || args.prompt_only;
+    if args.profile_writes || args.summary_json.is_some() {
+        utils::start_write_profile();
+    }
+    let package_name = utils::package_name(&args.name, &args.language);
+    if !quiet {
+        info!(
+            "Project name: `{}`, package name: `{}`",
+            args.name, package_name
+        );
+    }
+    let prompt = project_prompt(args);
+
+    let redactor = utils::Redactor::new(&args.redact);
+    if !quiet && args.template.is_none() {
+        info!("Sending prompt: {}", redactor.redact(&prompt));
+    }
 
     // Build the request to ChatGPT.
-    let client = Client::new();
-    let req = CreateChatCompletionRequestArgs::default()
-        .max_tokens(args.tokens)
-        .model(args.model)
+    let mut req = build_request(args, utils::SYSTEM_PROMPT, &prompt)?;
+
+    if args.prompt_only {
+        let messages = redactor.redact(&utils::format_messages(&req.messages));
+        match &args.prompt_out {
+            Some(prompt_out) => {
+                utils::create_file(prompt_out, &messages)?;
+            }
+            None => print!("{}", messages),
+        }
+        return Ok(Generation {
+            tokens: None,
+            files: 0,
+            writes: Vec::new(),
+        });
+    }
+
+    if args.dump_request_curl {
+        let mut body = if args.tool_mode {
+            tools::tool_request_body(&req, !args.no_docker)?
+        } else {
+            serde_json::to_value(&req)?
+        };
+        if args.stream {
+            body["stream"] = serde_json::Value::Bool(true);
+        }
+        println!(
+            "{}",
+            redactor.redact(&utils::curl_command(
+                client.api_base(),
+                &body,
+                &args.header,
+                args.json_indent
+            )?)
+        );
+        return Ok(Generation {
+            tokens: None,
+            files: 0,
+            writes: Vec::new(),
+        });
+    }
+
+    if args.raw {
+        let res = client.chat().create(req).await?;
+        println!("{}", redactor.redact(&first_choice(&res)?.message.content));
+        return Ok(Generation {
+            tokens: res.usage.map(|usage| usage.total_tokens),
+            files: 0,
+            writes: Vec::new(),
+        });
+    }
+
+    let project_name = args.name_scheme.dir_name(&args.name);
+    if args.name_scheme != utils::NameScheme::Plain {
+        info!("Project directory name: {}", project_name);
+    }
+    let project_path = match &args.monorepo_root {
+        Some(root) => format!("{}/{}", root, monorepo_member(args)),
+        None => format!("{}/{}", args.path, project_name),
+    };
+    report.path = Some(project_path.clone());
+
+    // Don't pay for a response that can't be written.
+    if !args.diff && !args.dry_run && !args.as_patch {
+        utils::check_writable(&project_path)?;
+        if let Some(backup_dir) = &args.backup_dir {
+            utils::check_writable(backup_dir)?;
+        }
+    }
+
+    if args.template.is_none() {
+        info!("Sending prompt to OpenAI, please wait... 🤖");
+    }
+    // Read before streaming writes anything, a generated .gitignore doesn't count.
+    let gitignore = if args.respect_gitignore {
+        utils::ProjectGitignore::read(&project_path)?
+    } else {
+        None
+    };
+    let mut parse_retries = 0;
+    let mut tokens = None;
+    let (content, mut contents, streamed, mut warnings) = match args.template {
+        Some(template) => {
+            info!(
+                "Generating the project from the `{}` template",
+                template.name()
+            );
+            let contents = template.render(&args.name, &package_name, !args.no_docker);
+            let content = utils::to_json(&contents, args.json_indent)?;
+            (content, contents, Vec::new(), utils::Warnings::default())
+        }
+        None => loop {
+            let mut warnings = utils::Warnings::default();
+            let (completion, streamed) = request_completion(
+                args,
+                http_client,
+                client,
+                &req,
+                &project_path,
+                gitignore.as_ref(),
+                &mut warnings,
+            )
+            .await?;
+            if let Some(used) = completion.tokens {
+                tokens = Some(tokens.unwrap_or(0) + used);
+                report.tokens = tokens;
+            }
+            if completion.finish_reason.as_deref() == Some("length") && !args.stream {
+                warnings.warn("The response was truncated because it reached the max tokens limit");
+            }
+
+            let content = completion.content;
+            info!("Got a response ✅ Attempting to decode the contents...");
+            info!("Response:\n{}", redactor.redact(&content));
+            match utils::decode_output(
+                &content,
+                &args.json_path,
+                !args.no_docker,
+                &required_fields(args),
+            ) {
+                Ok(contents) if args.interactive_refine => {
+                    print_refine_summary(&contents, preview_limit(args));
+                    match read_refinement()? {
+                        Refinement::Save => break (content, contents, streamed, warnings),
+                        Refinement::Quit => {
+                            info!("Nothing was written.");
+                            return Ok(Generation {
+                                tokens,
+                                files: 0,
+                                writes: Vec::new(),
+                            });
+                        }
+                        Refinement::Change(change) => {
+                            req.messages.push(
+                                ChatCompletionRequestMessageArgs::default()
+                                    .role(Role::Assistant)
+                                    .content(&content)
+                                    .build()?,
+                            );
+                            req.messages.push(
+                                ChatCompletionRequestMessageArgs::default()
+                                    .role(Role::User)
+                                    .content(format!("Change the project as follows and respond with the complete project as a valid Json object matching the same output schema: {}", change))
+                                    .build()?,
+                            );
+                            info!("Sending the refinement to OpenAI, please wait... 🤖");
+                        }
+                    }
+                }
+                Ok(contents) => break (content, contents, streamed, warnings),
+                Err(_) if utils::is_refusal(&content) => {
+                    anyhow::bail!("The model declined this request: {}", content.trim());
+                }
+                Err(e) => {
+                    if args.on_parse_failure.saves() {
+                        let saved = utils::save_failed_response(
+                            &args.path,
+                            &args.name,
+                            &redactor.redact(&content),
+                        )?;
+                        info!("Saved the response that failed to decode to `{}`", saved);
+                    }
+                    let max_retries = match args.on_parse_failure {
+                        utils::ParseFailure::SaveAndRetry => args.max_retries_on_parse.max(1),
+                        _ => args.max_retries_on_parse,
+                    };
+                    if parse_retries >= max_retries {
+                        info!(
+                            "Failed to decode the contents, please try again. Sometimes OpenAI returns invalid JSON."
+                        );
+                        return Err(e);
+                    }
+                    parse_retries += 1;
+                    info!(
+                        "Failed to decode the contents ({}), asking again ({}/{})... 🤖",
+                        e, parse_retries, max_retries
+                    );
+                }
+            }
+        },
+    };
+    if !args.allow_empty_project && streamed.is_empty() && utils::is_empty_project(&contents) {
+        anyhow::bail!("The model returned an empty project");
+    }
+    info!("Success, the robot has obeyed our orders.\n");
+
+    // When streaming the joke was already printed while waiting.
+    if wants_joke(args) && !args.stream {
+        if let Some(joke) = &contents.joke {
+            info!("{} 🤡\n", joke);
+        }
+    }
+
+    if let Some(explanation) = &contents.explanation {
+        info!("Explanation:\n{}\n", explanation);
+        if args.explain_to_readme {
+            contents.readme = format!(
+                "{}\n\n## Architecture\n\n{}\n",
+                contents.readme.trim_end(),
+                explanation
+            );
+        }
+    } else if args.explain || args.explain_to_readme {
+        warnings.warn("The model didn't return an explanation");
+    }
+
+    if let Some(makefile) = &contents.makefile {
+        let mut targets = vec!["build", "run", "test"];
+        if args.dev_dockerfile {
+            targets.push("dev");
+        }
+        for target in &args.make_target {
+            if !targets.contains(&target.as_str()) {
+                targets.push(target);
+            }
+        }
+        let missing_targets = utils::missing_make_targets(makefile, &targets);
+        if !missing_targets.is_empty() {
+            warnings.warn(format!(
+                "The generated Makefile is missing the following targets: {}",
+                missing_targets.join(", ")
+            ));
+        }
+    }
+    let missing_sections = utils::missing_readme_sections(&contents.readme);
+    if !missing_sections.is_empty() {
+        warnings.warn(format!(
+            "The generated README is missing instructions on how to: {}",
+            missing_sections.join(", ")
+        ));
+    }
+    if let Some(width) = args.wrap_width {
+        contents.readme = utils::wrap_markdown(&contents.readme, width);
+    }
+    for file in utils::project_files(&contents) {
+        if file.contents.trim().is_empty() {
+            warnings.warn(format!("The generated `{}` is empty", file.name));
+        } else if let Some(reason) = utils::binary_content(&file.contents) {
+            warnings.warn(format!(
+                "The generated `{}` {}, it may not be a text file",
+                file.name, reason
+            ));
+        }
+    }
+    if args.compose
+        && !contents
+            .makefile
+            .as_ref()
+            .is_some_and(|m| m.contains("compose"))
+    {
+        warnings.warn("The generated Makefile doesn't use docker compose");
+    }
+    utils::skip_reserved_files(&mut contents.source_files, &mut warnings);
+    // Source files written while streaming don't need to be written again, they are matched on
+    // the name from the response since the file name options may have renamed them. They were
+    // already moved to the expected paths.
+    contents.source_files.retain(|source_file| {
+        !streamed
+            .iter()
+            .any(|(decoded, _)| *decoded == source_file.name)
+    });
+    let mut expected = expected_files(args);
+    let streamed_paths: Vec<&str> = streamed
+        .iter()
+        .map(|(decoded, _)| utils::expected_path(decoded, &expected).unwrap_or(decoded))
+        .collect();
+    expected.retain(|path| !streamed_paths.contains(path));
+    utils::expect_files(&mut contents.source_files, &expected, &mut warnings);
+    if let Some(root) = &args.monorepo_root {
+        for edit in utils::workspace_edits(root, &monorepo_member(args)) {
+            warnings.warn(edit);
+        }
+    }
+    report.warnings = warnings.messages().to_vec();
+    warnings.check(args.strict)?;
+
+    info!("Generating the project files... 🤖");
+
+    if !args.preserve_order {
+        contents.source_files.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    prepare_source_files(args, &mut contents.source_files, &mut warnings)?;
+    if let Some(source_file) = contents.source_files.iter().find(|source_file| {
+        streamed
+            .iter()
+            .any(|(_, written)| *written == source_file.name)
+    }) {
+        anyhow::bail!(
+            "The file name options make several source files end up as `{}`",
+            source_file.name
+        );
+    }
+    if let Some(gitignore) = &gitignore {
+        gitignore.skip_ignored(&mut contents.source_files, &mut warnings);
+    }
+    output_processors(args).apply(&mut contents)?;
+
+    if args.as_patch {
+        let patch = diff::project_patch(&project_path, &contents)?;
+        match &args.patch_out {
+            Some(patch_out) => {
+                utils::create_file(patch_out, &patch)?;
+            }
+            None => print!("{}", patch),
+        }
+        return Ok(Generation {
+            tokens,
+            files: 0,
+            writes: Vec::new(),
+        });
+    }
+    if args.dry_run {
+        diff::print_dry_run(&project_path, &contents, preview_limit(args), args.diff)?;
+        return Ok(Generation {
+            tokens,
+            files: 0,
+            writes: Vec::new(),
+        });
+    }
+    if args.diff {
+        diff::print_project_diff(&project_path, &contents, preview_limit(args))?;
+        return Ok(Generation {
+            tokens,
+            files: 0,
+            writes: Vec::new(),
+        });
+    }
+    let mut generation = Generation {
+        tokens,
+        files: streamed.len() + utils::project_files(&contents).len(),
+        writes: Vec::new(),
+    };
+
+    info!("Creating project folder `{}`", project_path);
+    fs::create_dir_all(&project_path)?;
+
+    // Actually create the files.
+    let options = write_options(args);
+    for (name, file_contents) in [
+        ("Dockerfile", &contents.dockerfile),
+        ("Makefile", &contents.makefile),
+    ] {
+        if let Some(file_contents) = file_contents {
+            utils::write_project_file(&project_path, name, file_contents, &options)?;
+        }
+    }
+    utils::write_project_file(&project_path, "README.md", &contents.readme, &options)?;
+    let res = utils::create_source_files(
+        &project_path,
+        &contents.source_files,
+        &options,
+        args.write_concurrency,
+    )
+    .await;
+    report.warnings = warnings.messages().to_vec();
+    res?;
+    warnings.print_summary();
+    let tool_files = utils::tool_files(&contents);
+    if generation.files > tool_files.len() {
+        info!(
+            "{} file(s) were generated by the model, review them before running the project",
+            generation.files - tool_files.len()
+        );
+    }
+    if !tool_files.is_empty() {
+        info!(
+            "{} file(s) were added by project-pilot: {}",
+            tool_files.len(),
+            tool_files.join(", ")
+        );
+    }
+    if let Some(backup_dir) = &options.backup_dir {
+        if Path::new(backup_dir).exists() {
+            info!(
+                "Backups of the replaced files were saved in `{}`",
+                backup_dir
+            );
+        }
+    }
+    warnings.check(args.strict)?;
+
+    if args.keep_raw {
+        let pilot_path = format!("{}/.project-pilot", project_path);
+        fs::create_dir_all(&pilot_path)?;
+        utils::create_file(
+            &format!("{}/raw-response.txt", pilot_path),
+            &redactor.redact(&content),
+        )?;
+        let metadata = serde_json::json!({ "args": args, "prompt": &prompt });
+        utils::create_file(
+            &format!("{}/metadata.json", pilot_path),
+            &redactor.redact(&utils::to_json(&metadata, args.json_indent)?),
+        )?;
+    }
+
+    if args.echo_args {
+        let pilot_path = format!("{}/.project-pilot", project_path);
+        fs::create_dir_all(&pilot_path)?;
+        let invocation = format!(
+            "Command line:\n{}\n\nResolved arguments:\n{}\n",
+            utils::shell_command(masked_args()),
+            utils::to_json(args, args.json_indent)?
+        );
+        utils::create_file(
+            &format!("{}/invocation.txt", pilot_path),
+            &redactor.redact(&invocation),
+        )?;
+    }
+
+    if args.profile_writes {
+        generation.writes = utils::take_write_profile();
+        print_write_profile(&generation.writes);
+    }
+
+    if let Some(workdir) = &args.workdir {
+        if !Path::new(&project_path).join(workdir).is_dir() {
+            anyhow::bail!(
+                "The --workdir `{}` doesn't exist in the generated project",
+                workdir
+            );
+        }
+    }
+
+    // Explain how to use the generated project.
+    info!("Project files generated successfully ✅\n");
+    if !args.no_disclaimer {
+        info!("Disclaimer: This project was generated by a robot, please review the code before executing it.\n");
+    }
+    if args.no_docker {
+        info!("To execute the project, follow the instructions in its README:\n");
+    } else {
+        info!("To execute the project, run the following commands:\n");
+    }
+    let cd_path = match &args.monorepo_root {
+        Some(_) => project_path.clone(),
+        None => project_name,
+    };
+    match &args.workdir {
+        Some(workdir) => info!("cd {}/{}", cd_path, workdir),
+        None => info!("cd {}", cd_path),
+    }
+    if !args.no_docker {
+        info!("make build");
+        info!("make run");
+    }
+
+    let follow_up_steps = utils::follow_up_steps(&contents);
+    if !follow_up_steps.is_empty() {
+        info!("\nManual follow-up (best-effort, based on the README and Dockerfile):");
+        for step in follow_up_steps {
+            info!("- [ ] {}", step);
+        }
+    }
+    if args.compact_output {
+        println!("{}", project_path);
+    }
+
+    Ok(generation)
+}
+
+// Generate a known trivial project to a temporary directory and build it.
+async fn self_test(
+    args: &Args,
+    http_client: &reqwest::Client,
+    client: &Client,
+) -> anyhow::Result<()> {
+    let mut test_args = args.clone();
+    test_args.description = "An app that writes 'hello world' to the terminal".to_string();
+    test_args.name = "hello-world".to_string();
+    let test_dir = env::temp_dir().join(format!(
+        "project-pilot-self-test-{}",
+        utils::unique_suffix()
+    ));
+    test_args.path = test_dir.to_string_lossy().into_owned();
+    let mut build_dir = test_dir.join(&test_args.name);
+    if let Some(workdir) = &args.workdir {
+        build_dir.push(workdir);
+    }
+
+    let res = generate(&test_args, http_client, client, &mut Default::default())
+        .await
+        .and_then(|_| make_build(&build_dir));
+    if test_dir.exists() {
+        fs::remove_dir_all(&test_dir)?;
+    }
+
+    match res {
+        Ok(()) => {
+            info!("\nSelf test passed ✅");
+            Ok(())
+        }
+        Err(e) => Err(e.context("Self test failed")),
+    }
+}
+
+fn print_write_profile(writes: &[utils::FileWrite]) {
+    let total: Duration = writes.iter().map(|write| write.duration).sum();
+    info!(
+        "\nWrote {} file(s) in {:.2} ms:",
+        writes.len(),
+        total.as_secs_f64() * 1000.0
+    );
+    for write in writes {
+        info!(
+            "{:>10.2} ms  {} ({} bytes)",
+            write.duration.as_secs_f64() * 1000.0,
+            write.path,
+            write.bytes
+        );
+    }
+}
+
+fn make_build(dir: &Path) -> anyhow::Result<()> {
+    info!("\nRunning `make build` in `{}`", dir.display());
+    let status = std::process::Command::new("make")
+        .arg("build")
+        .current_dir(dir)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("`make build` failed with {}", status);
+    }
+    Ok(())
+}
+
+fn parse_rename(rule: &str) -> Result<(String, String), String> {
+    match rule.split_once('=') {
+        Some((old, new)) if !old.is_empty() => Ok((old.to_string(), new.to_string())),
+        _ => Err("expected `OLD=NEW` with a non empty `OLD`".to_string()),
+    }
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| "expected `Key: Value`".to_string())?;
+    let (name, value) = (name.trim(), value.trim());
+    reqwest::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| format!("invalid header name `{}`", name))?;
+    reqwest::header::HeaderValue::from_str(value)
+        .map_err(|_| format!("invalid value for header `{}`", name))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_make_target(target: &str) -> Result<String, String> {
+    let valid = !target.starts_with(['.', '-'])
+        && target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if target.is_empty() || !valid {
+        return Err("expected a target name made of letters, digits, `-`, `_` and `.`".to_string());
+    }
+    Ok(target.to_string())
+}
+
+fn parse_workdir(workdir: &str) -> Result<String, String> {
+    let path = Path::new(workdir);
+    if path.is_absolute()
+        || path
+            .components()
+            .any(|c| c == std::path::Component::ParentDir)
+    {
+        return Err("expected a path inside the project root".to_string());
+    }
+    Ok(workdir.trim_end_matches('/').to_string())
+}
+
+fn parse_top_p(top_p: &str) -> Result<f32, String> {
+    let top_p: f32 = top_p
+        .parse()
+        .map_err(|_| format!("`{}` isn't a number", top_p))?;
+    if !(0.0..=1.0).contains(&top_p) {
+        return Err(format!("{} is not in the range 0.0 to 1.0", top_p));
+    }
+    Ok(top_p)
+}
+
+fn parse_penalty(penalty: &str) -> Result<f32, String> {
+    let penalty: f32 = penalty
+        .parse()
+        .map_err(|_| format!("`{}` isn't a number", penalty))?;
+    if !(-2.0..=2.0).contains(&penalty) {
+        return Err(format!("{} is not in the range -2.0 to 2.0", penalty));
+    }
+    Ok(penalty)
+}
+
+fn parse_json_pointer(pointer: &str) -> Result<String, String> {
+    if pointer.is_empty() || pointer.starts_with('/') {
+        Ok(pointer.to_string())
+    } else {
+        Err("a JSON Pointer must be empty or start with `/`".to_string())
+    }
+}
+
+// Build a chat request from a system and user prompt with the model settings of the cmdline.
+fn build_request(
+    args: &Args,
+    system: &str,
+    prompt: &str,
+) -> anyhow::Result<CreateChatCompletionRequest> {
+    let mut req_builder = CreateChatCompletionRequestArgs::default();
+    req_builder
+        // The async-openai request type only fits 16 bit max tokens, checked in `main`.
+        .max_tokens(u16::try_from(args.tokens).unwrap_or(u16::MAX))
+        .model(&args.model)
         .messages([
             ChatCompletionRequestMessageArgs::default()
                 .role(Role::System)
-                .content(
-                    "You are a helpful programming assistant.
-                    You are expected to process an application description and generate the files and steps necessary to create the application using your language model.
-                    You can only respond with a Json object that matches the provided output schema.
-                    The returned Json can include an array of objects as defined by the output schema.
-                    You are not allowed to return anything but a valid Json object.",
-                )
+                .content(system)
                 .build()?,
             ChatCompletionRequestMessageArgs::default()
                 .role(Role::User)
                 .content(prompt)
                 .build()?,
-        ])
-        .build()?;
+        ]);
+    let params = args.model_params_file.clone().unwrap_or_default();
+    if let Some(temperature) = params.temperature {
+        req_builder.temperature(temperature);
+    }
+    if let Some(top_p) = args.top_p.or(params.top_p) {
+        req_builder.top_p(top_p);
+    }
+    if let Some(presence_penalty) = args.presence_penalty.or(params.presence_penalty) {
+        req_builder.presence_penalty(presence_penalty);
+    }
+    if let Some(frequency_penalty) = args.frequency_penalty.or(params.frequency_penalty) {
+        req_builder.frequency_penalty(frequency_penalty);
+    }
+    let stop = if args.stop.is_empty() {
+        &params.stop
+    } else {
+        &args.stop
+    };
+    if !stop.is_empty() {
+        req_builder.stop(Stop::StringArray(stop.clone()));
+    }
+    let mut logit_bias = params.request_logit_bias();
+    if args.no_fences {
+        match utils::fence_logit_bias(&args.model) {
+            Some(fence_logit_bias) => logit_bias.extend(fence_logit_bias),
+            None => info!(
+                "The tokenizer of `{}` is unknown, --no-fences has no effect",
+                args.model
+            ),
+        }
+    }
+    if !logit_bias.is_empty() {
+        req_builder.logit_bias(logit_bias);
+    }
+    Ok(req_builder.build()?)
+}
 
-    println!("Sending prompt to OpenAI, please wait... 🤖");
-    let res = client.chat().create(req).await?;
-    println!("Got a response ✅ Attempting to decode the contents...");
-    println!("Response:\n{}", &res.choices[0].message.content);
-    let contents: utils::OutputJson = serde_json::from_str(&res.choices[0].message.content)
-        .map_err(|e| {
-            println!(
-                "Failed to decode the contents, please try again. Sometimes OpenAI returns invalid JSON."
-            );
-            e
-        })?;
-    println!("Success, the robot has obeyed our orders.\n");
+// The prompt asking for the project described by the cmdline arguments.
+fn project_prompt(args: &Args) -> String {
+    utils::generate_prompt(
+        &args.name,
+        &args.description,
+        &args.language,
+        !args.no_docker,
+        &prompt_requirements(args),
+    )
+}
 
-    println!("Generating the project files... 🤖");
+// Print the estimated cost of generating the project, assuming every requested completion
+// uses all the --tokens, and return it when the price of the model is known.
+fn estimate_cost(args: &Args) -> anyhow::Result<Option<f64>> {
+    let req = build_request(args, utils::SYSTEM_PROMPT, &project_prompt(args))?;
+    let prompt_tokens = utils::estimate_tokens(&utils::format_messages(&req.messages));
+    let completion_tokens = args.tokens * u32::from(args.n);
+    let cost = utils::model_price(&args.model).map(|(input, output)| {
+        (f64::from(prompt_tokens) * input + f64::from(completion_tokens) * output) / 1_000_000.0
+    });
+    match cost {
+        Some(cost) => info!(
+            "`{}`: ~{} prompt and at most {} completion tokens with `{}`, at most ${:.4}",
+            args.name, prompt_tokens, completion_tokens, args.model, cost
+        ),
+        None => info!(
+            "`{}`: ~{} prompt and at most {} completion tokens, the price of `{}` is unknown",
+            args.name, prompt_tokens, completion_tokens, args.model
+        ),
+    }
+    Ok(cost)
+}
 
-    let project_name = args.name;
-    let project_path = format!("{}/{}", args.path, project_name);
-    println!("Creating project folder `{}`", project_path);
-    fs::create_dir_all(&project_path)?;
+// Whether to ask for the joke of --joke-first, --no-disclaimer leaves it out.
+fn wants_joke(args: &Args) -> bool {
+    args.joke_first && !args.no_disclaimer
+}
 
-    // Actually create the files.
-    utils::create_file(
-        &format!("{}/Dockerfile", project_path),
-        &contents.dockerfile,
-    )?;
-    utils::create_file(&format!("{}/Makefile", project_path), &contents.makefile)?;
-    utils::create_file(&format!("{}/README.md", project_path), &contents.readme)?;
-    utils::create_source_files(&project_path, contents.source_files)?;
+// Collect the additional requirements for the prompt from the cmdline arguments.
+fn prompt_requirements(args: &Args) -> Vec<String> {
+    let mut requirements = vec![format!(
+        "Use \"{}\" as the package name in package manifests and wherever an identifier for the project is needed, the project name is only meant for humans.",
+        utils::package_name(&args.name, &args.language)
+    )];
+    if let Some(preset) = args.preset {
+        requirements.push(preset.instructions().to_string());
+    }
+    if let Some(author) = &args.author {
+        requirements.push(format!(
+            "Use \"{}\" as the author in the package manifest metadata.",
+            author
+        ));
+    }
+    if let Some(summary) = &args.summary {
+        requirements.push(format!(
+            "Use \"{}\" as the description in the package manifest metadata.",
+            summary
+        ));
+    }
+    if args.explain || args.explain_to_readme {
+        requirements.push(
+            "Add an \"explanation\" field to the output json explaining the architectural choices you made."
+                .to_string(),
+        );
+    }
+    if args.devcontainer {
+        requirements.push(
+            "Include a source file named \".devcontainer/devcontainer.json\" with a dev container configuration that builds from the Dockerfile."
+                .to_string(),
+        );
+    }
+    if args.compose {
+        requirements.push(
+            "Include a source file named \"docker-compose.yml\" that runs the application together with the services it needs, like databases or caches."
+                .to_string(),
+        );
+        requirements.push(
+            "The Makefile targets must use docker compose with the docker-compose.yml instead of plain docker commands."
+                .to_string(),
+        );
+    }
+    if let Some(config) = args
+        .language_map
+        .as_ref()
+        .and_then(|language_map| language_map.get(&args.language))
+    {
+        if let Some(extension) = &config.extension {
+            requirements.push(format!(
+                "Use the \".{}\" file extension for the source files.",
+                extension.trim_start_matches('.')
+            ));
+        }
+        if let Some(base_image) = config.base_image.as_ref().filter(|_| !args.no_docker) {
+            requirements.push(format!(
+                "Use \"{}\" as the base image in the Dockerfile.",
+                base_image
+            ));
+        }
+    }
+    if args.k8s {
+        requirements.push(
+            "Add \"k8s/deployment.yaml\" and \"k8s/service.yaml\" source files with a Kubernetes Deployment running the image built from the Dockerfile and a Service exposing the ports the application listens on."
+                .to_string(),
+        );
+    }
+    if args.helm {
+        requirements.push(
+            "Add a minimal Helm chart in the \"chart\" directory: \"chart/Chart.yaml\", \"chart/values.yaml\" with the image built from the Dockerfile and the ports the application listens on, and \"chart/templates/deployment.yaml\" and \"chart/templates/service.yaml\" source files using those values."
+                .to_string(),
+        );
+    }
+    if args.openapi {
+        requirements.push(
+            "Add an \"openapi.yaml\" source file with an OpenAPI 3 document describing every endpoint of the API, with its parameters, request bodies and responses."
+                .to_string(),
+        );
+    }
+    if args.precommit {
+        requirements.push(
+            "Add a \".pre-commit-config.yaml\" source file configuring pre-commit with the usual formatting and linting hooks for the language, pinned to released versions."
+                .to_string(),
+        );
+    }
+    if args.gitignore && utils::gitignore_template(&args.language).is_none() {
+        requirements.push(
+            "Add a \".gitignore\" source file that ignores the dependency directories, build output, environment files and editor files of the language."
+                .to_string(),
+        );
+    }
+    if args.dockerignore {
+        requirements.push(
+            "Add a \".dockerignore\" source file that excludes the dependency directories, build output, version control and editor files of the language from the docker build context."
+                .to_string(),
+        );
+    }
+    if args.dev_dockerfile {
+        requirements.push(format!(
+            "Add a \"{}\" source file for local development that installs the development dependencies and runs the application with hot reload, with the project directory mounted as a volume. Add a \"make dev\" target to the Makefile that builds and runs it (make sure that docker cleans up after itself).",
+            utils::DEV_DOCKERFILE
+        ));
+    }
+    if !args.make_target.is_empty() {
+        let targets: Vec<String> = args
+            .make_target
+            .iter()
+            .map(|target| format!("\"make {}\"", target))
+            .collect();
+        requirements.push(format!(
+            "Also add the following targets to the Makefile, implemented with the idiomatic tools of the language: {}.",
+            targets.join(", ")
+        ));
+    }
+    if args.tooling {
+        let lint_config = match utils::lint_config_file(&args.language) {
+            Some(file) => format!("a \"{}\" file", file),
+            None => "the config file of the idiomatic linter".to_string(),
+        };
+        requirements.push(format!(
+            "Include an \".editorconfig\" file with the indentation conventions of the language and {} for linting the source code.",
+            lint_config
+        ));
+    }
+    if args.monorepo_root.is_some() {
+        requirements.push(format!(
+            "The project is a member of a monorepo located at \"{}\", generate manifests that work as a workspace member (e.g. a Cargo workspace member or an npm workspace package) and don't define a workspace of their own.",
+            monorepo_member(args)
+        ));
+    }
+    if wants_joke(args) {
+        requirements.push(
+            "Start the output json with a \"joke\" field containing a short programming joke."
+                .to_string(),
+        );
+    }
+    requirements
+}
 
-    // Explain how to use the generated project.
-    println!("Project files generated successfully ✅\n");
-    println!("Disclaimer: This project was generated by a robot, please review the code before executing it.\n");
-    println!("To execute the project, run the following commands:\n");
-    println!("cd {}", project_name);
-    println!("make build");
-    println!("make run");
+// The source files the model was asked to generate on top of the defaults.
+fn expected_files(args: &Args) -> Vec<&'static str> {
+    let mut expected = Vec::new();
+    if args.devcontainer {
+        expected.push(".devcontainer/devcontainer.json");
+    }
+    if args.compose {
+        expected.push("docker-compose.yml");
+    }
+    if args.k8s {
+        expected.extend(["k8s/deployment.yaml", "k8s/service.yaml"]);
+    }
+    if args.helm {
+        expected.extend([
+            "chart/Chart.yaml",
+            "chart/values.yaml",
+            "chart/templates/deployment.yaml",
+            "chart/templates/service.yaml",
+        ]);
+    }
+    if args.openapi {
+        expected.push("openapi.yaml");
+    }
+    if args.precommit {
+        expected.push(".pre-commit-config.yaml");
+    }
+    if args.gitignore && utils::gitignore_template(&args.language).is_none() {
+        expected.push(".gitignore");
+    }
+    if args.dockerignore {
+        expected.push(".dockerignore");
+    }
+    if args.dev_dockerfile {
+        expected.push(utils::DEV_DOCKERFILE);
+    }
+    if args.tooling {
+        expected.push(".editorconfig");
+        expected.extend(utils::lint_config_file(&args.language));
+    }
+    expected
+}
+
+// The fields the response must contain according to the cmdline arguments.
+fn required_fields(args: &Args) -> Vec<utils::OutputField> {
+    use utils::OutputField::*;
+    match &args.require_fields {
+        Some(fields) => fields.clone(),
+        None if args.no_docker => vec![Readme, SourceFiles],
+        None => vec![Dockerfile, Makefile, Readme, SourceFiles],
+    }
+}
+
+// The number of lines of each file shown in previews, `None` shows all of them.
+fn preview_limit(args: &Args) -> Option<usize> {
+    usize::try_from(args.preview_lines).ok()
+}
 
+// Path of the project relative to the root of the monorepo.
+fn monorepo_member(args: &Args) -> String {
+    format!("{}/{}", utils::MONOREPO_PACKAGES_DIR, args.name)
+}
+
+// How files are written into the project folder according to the cmdline arguments.
+fn write_options(args: &Args) -> utils::WriteOptions {
+    utils::WriteOptions {
+        skip_existing: args.skip_existing_files,
+        // Keep the backups of each project apart when generating a batch.
+        backup_dir: args
+            .backup_dir
+            .as_ref()
+            .map(|backup_dir| format!("{}/{}", backup_dir, args.name)),
+        verify: args.verify_writes,
+    }
+}
+
+// The processors requested on the cmdline, applied to the whole project before writing it.
+fn output_processors(args: &Args) -> processors::Processors {
+    let mut processors = processors::Processors::default();
+    if let Some(license) = &args.spdx_license {
+        processors.register(processors::LicenseStamp {
+            license: license.clone(),
+            language_map: args.language_map.clone().unwrap_or_default(),
+        });
+    }
+    if args.gitignore {
+        if let Some(contents) = utils::gitignore_template(&args.language) {
+            processors.register(processors::Gitignore { contents });
+        }
+    }
+    // Registered last, so it lists the files as they are written.
+    if args.manifest {
+        processors.register(processors::Manifest {
+            indent: args.json_indent,
+        });
+    }
+    processors
+}
+
+// Apply the transformations requested on the cmdline to the source files before writing them.
+fn prepare_source_files(
+    args: &Args,
+    source_files: &mut [utils::SourceFile],
+    warnings: &mut utils::Warnings,
+) -> anyhow::Result<()> {
+    if args.normalize_paths {
+        utils::normalize_file_names(source_files)?;
+    }
+    if !args.rename.is_empty() {
+        utils::rename_source_files(source_files, &args.rename)?;
+    }
+    if args.ascii_filenames {
+        utils::ascii_file_names(source_files)?;
+    }
+    if args.validate_configs {
+        utils::validate_configs(source_files, args.json_indent, warnings);
+    }
+    if args.stamp {
+        utils::stamp_source_files(
+            source_files,
+            &args.description,
+            &args.model,
+            args.language_map.as_ref().unwrap_or(&Default::default()),
+            args.comment_style,
+        );
+    }
     Ok(())
 }
+
+// The first choice of a response, OpenAI only returns more when asked for several.
+fn first_choice(res: &CreateChatCompletionResponse) -> anyhow::Result<&ChatChoice> {
+    res.choices
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("The response from OpenAI didn't contain any choices"))
+}
+
+// What the user wants to do with the project in interactive refine mode.
+enum Refinement {
+    Save,
+    Quit,
+    Change(String),
+}
+
+fn print_refine_summary(contents: &utils::OutputJson, preview_lines: Option<usize>) {
+    info!("\nThe project contains the following files:");
+    for file in utils::project_files(contents) {
+        info!("- {} ({} lines)", file.name, file.contents.lines().count());
+        if preview_lines != Some(0) {
+            print!("{}", utils::preview(&file.contents, preview_lines));
+        }
+    }
+    if let Some(explanation) = &contents.explanation {
+        info!("\n{}", explanation);
+    }
+}
+
+fn read_refinement() -> anyhow::Result<Refinement> {
+    loop {
+        info!("\nDescribe a change, or type `:save` to write the project or `:quit` to exit:");
+        print!("> ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(Refinement::Quit);
+        }
+        match line.trim() {
+            "" => continue,
+            ":save" => return Ok(Refinement::Save),
+            ":quit" | ":q" => return Ok(Refinement::Quit),
+            change => return Ok(Refinement::Change(change.to_string())),
+        }
+    }
+}
+
+fn read_choice(count: usize) -> anyhow::Result<usize> {
+    loop {
+        print!("Choose the variant to write (1-{}): ", count);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            anyhow::bail!("No variant was chosen");
+        }
+        match line.trim().parse::<usize>() {
+            Ok(choice) if (1..=count).contains(&choice) => return Ok(choice - 1),
+            _ => info!("Expected a number from 1 to {}", count),
+        }
+    }
+}
+
+// Request the --n variants and return the chosen one among those that decode. Without any
+// valid variant the first one is returned, for the caller to report why it doesn't decode.
+async fn choose_variant(
+    client: &Client,
+    req: &CreateChatCompletionRequest,
+    args: &Args,
+) -> anyhow::Result<utils::Completion> {
+    let mut req = req.clone();
+    req.n = Some(args.n);
+    let res = client.chat().create(req).await?;
+    let tokens = res.usage.as_ref().map(|usage| usage.total_tokens);
+
+    let variants: Vec<(&ChatChoice, utils::OutputJson)> = res
+        .choices
+        .iter()
+        .filter_map(|choice| {
+            utils::decode_output(
+                &choice.message.content,
+                &args.json_path,
+                !args.no_docker,
+                &required_fields(args),
+            )
+            .ok()
+            .map(|contents| (choice, contents))
+        })
+        .collect();
+    let choice = if variants.is_empty() {
+        first_choice(&res)?
+    } else {
+        info!(
+            "{} of {} variant(s) are valid:",
+            variants.len(),
+            res.choices.len()
+        );
+        for (i, (_, contents)) in variants.iter().enumerate() {
+            info!("{}) {}", i + 1, utils::project_summary(contents));
+        }
+        let index = if args.auto_choose.is_some() || variants.len() == 1 {
+            0
+        } else if !io::stdin().is_terminal() {
+            info!("Not asking which variant to write, stdin isn't a terminal");
+            0
+        } else {
+            read_choice(variants.len())?
+        };
+        info!("Writing variant {}", index + 1);
+        variants[index].0
+    };
+
+    Ok(utils::Completion {
+        content: choice.message.content.clone(),
+        finish_reason: choice.finish_reason.clone(),
+        tokens,
+    })
+}
+
+// Send the request the way the cmdline arguments ask for, retrying network failures.
+async fn request_completion(
+    args: &Args,
+    http_client: &reqwest::Client,
+    client: &Client,
+    req: &CreateChatCompletionRequest,
+    project_path: &str,
+    gitignore: Option<&utils::ProjectGitignore>,
+    warnings: &mut utils::Warnings,
+) -> anyhow::Result<(utils::Completion, Vec<(String, String)>)> {
+    if let Some(command) = &args.response_command {
+        let completion = command_response(command, &req.messages).await?;
+        return Ok((completion, Vec::new()));
+    }
+
+    let mut retries = 0;
+    loop {
+        let res = if args.stream {
+            stream_response(client, req.clone(), args, project_path, gitignore, warnings).await
+        } else if args.n > 1 {
+            choose_variant(client, req, args)
+                .await
+                .map(|completion| (completion, Vec::new()))
+        } else if args.tool_mode {
+            tools::create_with_tool(http_client, client, req, !args.no_docker)
+                .await
+                .map(|completion| (completion, Vec::new()))
+        } else {
+            create_with_continuations(client, req.clone(), args.max_continuations)
+                .await
+                .map(|completion| (completion, Vec::new()))
+        };
+
+        match res {
+            Err(e) if retries < args.max_retries && is_network_error(&e) => {
+                retries += 1;
+                info!(
+                    "Request failed ({}), retrying ({}/{})... 🤖",
+                    e, retries, args.max_retries
+                );
+                tokio::time::sleep(Duration::from_secs(1 << retries)).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+// Run `command` with the conversation on stdin, its stdout is the response.
+async fn command_response(
+    command: &str,
+    messages: &[ChatCompletionRequestMessage],
+) -> anyhow::Result<utils::Completion> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run `{}`: {}", command, e))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = utils::format_messages(messages);
+    // Write while reading the output, so that a command answering before it read the whole
+    // conversation doesn't block, nor one that doesn't read it at all.
+    let write = async move {
+        let _ = stdin.write_all(input.as_bytes()).await;
+    };
+    let ((), output) = tokio::join!(write, child.wait_with_output());
+    let output = output?;
+    if !output.status.success() {
+        anyhow::bail!("`{}` failed with {}", command, output.status);
+    }
+    Ok(utils::Completion {
+        content: String::from_utf8(output.stdout)
+            .map_err(|_| anyhow::anyhow!("The output of `{}` isn't valid UTF-8", command))?,
+        finish_reason: None,
+        tokens: None,
+    })
+}
+
+// The cmdline arguments with the values of --header masked, they are often credentials.
+fn masked_args() -> Vec<String> {
+    let mask = |header: &str| match header.split_once(':') {
+        Some((name, _)) => format!("{}: ***", name.trim()),
+        None => "***".to_string(),
+    };
+    let mut masked = Vec::new();
+    let mut header_next = false;
+    for arg in env::args() {
+        if header_next {
+            masked.push(mask(&arg));
+            header_next = false;
+        } else if let Some(header) = arg.strip_prefix("--header=") {
+            masked.push(format!("--header={}", mask(header)));
+        } else {
+            header_next = arg == "--header";
+            masked.push(arg);
+        }
+    }
+    masked
+}
+
+// The HTTP client of every request, sending the extra `headers` along.
+fn http_client(headers: &[(String, String)]) -> anyhow::Result<reqwest::Client> {
+    let mut default_headers = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        default_headers.append(
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())?,
+            reqwest::header::HeaderValue::from_str(value)?,
+        );
+    }
+    Ok(reqwest::Client::builder()
+        .default_headers(default_headers)
+        .build()?)
+}
+
+// Fail fast on an invalid API key with a cheap request listing the models.
+async fn check_api_key(http_client: &reqwest::Client, client: &Client) -> anyhow::Result<()> {
+    let res = http_client
+        .get(format!("{}/models", client.api_base()))
+        .bearer_auth(client.api_key())
+        .send()
+        .await;
+    match res {
+        Ok(res) if res.status() == reqwest::StatusCode::UNAUTHORIZED => anyhow::bail!(
+            "Invalid or missing API key, make sure OPENAI_API_KEY is set to a valid key from https://platform.openai.com/account/api-keys"
+        ),
+        // Other problems are reported, and retried, by the actual request.
+        _ => Ok(()),
+    }
+}
+
+// Errors worth retrying: connection problems, timeouts, interrupted streams and server errors.
+fn is_network_error(e: &anyhow::Error) -> bool {
+    if e.downcast_ref::<reqwest::Error>().is_some() {
+        return true;
+    }
+    match e.downcast_ref::<OpenAIError>() {
+        Some(OpenAIError::Reqwest(_)) | Some(OpenAIError::StreamError(_)) => true,
+        Some(OpenAIError::ApiError(api_error)) => api_error.r#type == "server_error",
+        _ => false,
+    }
+}
+
+// Send the request and keep asking the model to continue while the response is truncated.
+// Returns the concatenated response with the finish reason of the last part.
+async fn create_with_continuations(
+    client: &Client,
+    mut req: CreateChatCompletionRequest,
+    max_continuations: u32,
+) -> anyhow::Result<utils::Completion> {
+    let mut content = String::new();
+    let mut tokens = None;
+    let mut continuations = 0;
+
+    loop {
+        let res = client.chat().create(req.clone()).await?;
+        if let Some(usage) = &res.usage {
+            tokens = Some(tokens.unwrap_or(0) + usage.total_tokens);
+        }
+        let choice = first_choice(&res)?;
+        content = utils::merge_continuation(&content, &choice.message.content);
+
+        if choice.finish_reason.as_deref() != Some("length") || continuations >= max_continuations {
+            return Ok(utils::Completion {
+                content,
+                finish_reason: choice.finish_reason.clone(),
+                tokens,
+            });
+        }
+
+        continuations += 1;
+        info!(
+            "The response was truncated, asking the model to continue ({}/{})... 🤖",
+            continuations, max_continuations
+        );
+        req.messages.push(
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::Assistant)
+                .content(&choice.message.content)
+                .build()?,
+        );
+        req.messages.push(
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::User)
+                .content("Continue exactly where you left off, even if that is in the middle of a string or token. Do not repeat anything and do not add any other words.")
+                .build()?,
+        );
+    }
+}
+
+// Stream the response, writing each source file as soon as it has been fully received.
+// Returns the complete response and the source files already written, by their name in the
+// response and the name they were written as. Files that
+// `gitignore` skips are left for the full response, where the skip is reported.
+async fn stream_response(
+    client: &Client,
+    req: CreateChatCompletionRequest,
+    args: &Args,
+    project_path: &str,
+    gitignore: Option<&utils::ProjectGitignore>,
+    warnings: &mut utils::Warnings,
+) -> anyhow::Result<(utils::Completion, Vec<(String, String)>)> {
+    let mut finish_reason = None;
+    let mut stream = client.chat().create_stream(req).await?;
+    let mut parser = stream::StreamParser::new();
+    let mut streamed = Vec::new();
+    let mut warned = false;
+    // Files are moved to the expected paths as they are written, like `expect_files` does.
+    let expected = expected_files(args);
+    let mut moved = Vec::new();
+
+    while let Some(res) = stream.next().await {
+        let res = match res {
+            Ok(res) => res,
+            // Not a network error, retrying would stream the written files a second time.
+            Err(e) if !streamed.is_empty() => anyhow::bail!(
+                "The response stream failed after writing {} file(s): {}",
+                streamed.len(),
+                e
+            ),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(reason) = res.choices.first().and_then(|c| c.finish_reason.clone()) {
+            if reason == "length" {
+                warnings.warn("The response was truncated because it reached the max tokens limit");
+            }
+            finish_reason = Some(reason);
+        }
+        let Some(delta) = res.choices.first().and_then(|c| c.delta.content.as_deref()) else {
+            continue;
+        };
+
+        let completed = parser.feed(delta);
+        for (key, value) in parser.take_fields() {
+            if key == "joke" && wants_joke(args) {
+                info!("While you wait: {} 🤡\n", value);
+            }
+        }
+        // Checks like the README ones need the whole response, which --strict waits for.
+        if args.strict {
+            continue;
+        }
+        for mut source_file in completed {
+            if streamed.is_empty() {
+                info!("Creating project folder `{}`", project_path);
+                fs::create_dir_all(project_path)?;
+            }
+            let decoded = source_file.name.clone();
+            if let Some(path) = utils::expected_path(&decoded, &expected) {
+                if !moved.contains(&path) {
+                    info!("Moving `{}` to `{}`", decoded, path);
+                    source_file.name = path.to_string();
+                    moved.push(path);
+                }
+            }
+            prepare_source_files(args, std::slice::from_mut(&mut source_file), warnings)?;
+            if gitignore.is_some_and(|g| g.skip_reason(&source_file.name).is_some()) {
+                continue;
+            }
+            if streamed
+                .iter()
+                .any(|(other, written)| *written == source_file.name && *other != decoded)
+            {
+                anyhow::bail!(
+                    "The file name options make several source files end up as `{}`",
+                    source_file.name
+                );
+            }
+            utils::create_source_file(project_path, &source_file, &write_options(args))?;
+            streamed.push((decoded, source_file.name));
+        }
+
+        if parser.failed() && !warned {
+            warnings.warn("Could not parse the streamed response incrementally, falling back to parsing the full response");
+            warned = true;
+        }
+    }
+
+    let completion = utils::Completion {
+        content: parser.buffer().to_string(),
+        finish_reason,
+        // Usage isn't reported for streamed responses.
+        tokens: None,
+    };
+    Ok((completion, streamed))
+}
@@ -0,0 +1,110 @@
+use std::fmt::Write;
+use std::fs;
+use std::time::Duration;
+
+use crate::{utils, Generation};
+
+const TOKEN_BUCKETS: [f64; 7] = [256.0, 512.0, 1024.0, 2048.0, 4096.0, 8192.0, 16384.0];
+const LATENCY_BUCKETS: [f64; 8] = [1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+const WRITE_BUCKETS: [f64; 7] = [0.0001, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    generated: u64,
+    failed: u64,
+    tokens: Vec<f64>,
+    latencies: Vec<f64>,
+    writes: Vec<f64>,
+}
+
+impl Metrics {
+    /// Record the outcome of generating one project, `None` when it failed.
+    pub fn record(&mut self, generation: Option<&Generation>, elapsed: Duration) {
+        match generation {
+            Some(generation) => {
+                self.generated += 1;
+                if let Some(tokens) = generation.tokens {
+                    self.tokens.push(tokens as f64);
+                }
+                self.writes.extend(
+                    generation
+                        .writes
+                        .iter()
+                        .map(|write| write.duration.as_secs_f64()),
+                );
+            }
+            None => self.failed += 1,
+        }
+        self.latencies.push(elapsed.as_secs_f64());
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "project_pilot_projects_generated_total",
+            "Projects generated successfully.",
+            self.generated,
+        );
+        counter(
+            &mut out,
+            "project_pilot_projects_failed_total",
+            "Projects that failed to generate.",
+            self.failed,
+        );
+        histogram(
+            &mut out,
+            "project_pilot_tokens",
+            "Tokens used per generated project.",
+            &TOKEN_BUCKETS,
+            &self.tokens,
+        );
+        histogram(
+            &mut out,
+            "project_pilot_generation_duration_seconds",
+            "Time spent generating each project.",
+            &LATENCY_BUCKETS,
+            &self.latencies,
+        );
+        // Only measured with --profile-writes.
+        if !self.writes.is_empty() {
+            histogram(
+                &mut out,
+                "project_pilot_file_write_duration_seconds",
+                "Time spent writing each file.",
+                &WRITE_BUCKETS,
+                &self.writes,
+            );
+        }
+        out
+    }
+
+    /// Write the metrics to `metrics_path`, through a temporary file so a textfile collector
+    /// never reads a partially written file.
+    pub fn write(&self, metrics_path: &str) -> anyhow::Result<()> {
+        let tmp_path = format!("{}.{}.tmp", metrics_path, utils::unique_suffix());
+        fs::write(&tmp_path, self.render())?;
+        fs::rename(&tmp_path, metrics_path)?;
+        info!("Wrote metrics to `{}`", metrics_path);
+        Ok(())
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, buckets: &[f64], values: &[f64]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for bucket in buckets {
+        let count = values.iter().filter(|v| *v <= bucket).count();
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bucket, count);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, values.len());
+    let _ = writeln!(out, "{}_sum {}", name, values.iter().sum::<f64>());
+    let _ = writeln!(out, "{}_count {}", name, values.len());
+}
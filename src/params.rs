@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::ops::RangeInclusive;
+
+use crate::MAX_STOP_SEQUENCES;
+
+/// Sampling parameters read from a JSON file, the cmdline flags take precedence.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelParams {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Rejected, the OpenAI client can't send a seed yet.
+    pub seed: Option<i64>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// Bias from -100 to 100 per token id.
+    #[serde(default)]
+    pub logit_bias: HashMap<String, i32>,
+}
+
+impl ModelParams {
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read model parameters `{}`: {}", path, e))?;
+        let params: ModelParams = serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid model parameters `{}`: {}", path, e))?;
+        params
+            .validate()
+            .map_err(|e| anyhow::anyhow!("Invalid model parameters `{}`: {}", path, e))?;
+        Ok(params)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        check_range("temperature", self.temperature, 0.0..=2.0)?;
+        check_range("top_p", self.top_p, 0.0..=1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0..=2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0..=2.0)?;
+        if self.seed.is_some() {
+            anyhow::bail!("`seed` isn't supported by the OpenAI client in use");
+        }
+        if self.stop.len() > MAX_STOP_SEQUENCES {
+            anyhow::bail!(
+                "at most {} stop sequences are allowed, got {}",
+                MAX_STOP_SEQUENCES,
+                self.stop.len()
+            );
+        }
+        for (token, bias) in &self.logit_bias {
+            if token.parse::<u32>().is_err() {
+                anyhow::bail!("the logit_bias key `{}` isn't a token id", token);
+            }
+            if !(-100..=100).contains(bias) {
+                anyhow::bail!(
+                    "the logit_bias of token {} is {}, not in the range -100 to 100",
+                    token,
+                    bias
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The `logit_bias` in the form of the request.
+    pub fn request_logit_bias(&self) -> HashMap<String, serde_json::Value> {
+        self.logit_bias
+            .iter()
+            .map(|(token, bias)| (token.clone(), serde_json::json!(bias)))
+            .collect()
+    }
+}
+
+fn check_range(name: &str, value: Option<f32>, range: RangeInclusive<f32>) -> anyhow::Result<()> {
+    match value {
+        Some(value) if !range.contains(&value) => anyhow::bail!(
+            "`{}` is {}, not in the range {:.1} to {:.1}",
+            name,
+            value,
+            range.start(),
+            range.end()
+        ),
+        _ => Ok(()),
+    }
+}
+
+pub fn parse_model_params(path: &str) -> Result<ModelParams, String> {
+    ModelParams::read(path).map_err(|e| e.to_string())
+}
@@ -0,0 +1,108 @@
+use serde::Serialize;
+
+use crate::languages::LanguageMap;
+use crate::utils::{self, FileOrigin, OutputJson, SourceFile};
+
+/// A transformation of the decoded project applied before it is written.
+pub trait OutputProcessor {
+    fn process(&self, output: &mut OutputJson) -> anyhow::Result<()>;
+}
+
+/// The registered processors, applied in the order they were registered.
+#[derive(Default)]
+pub struct Processors {
+    processors: Vec<Box<dyn OutputProcessor>>,
+}
+
+impl Processors {
+    pub fn register(&mut self, processor: impl OutputProcessor + 'static) -> &mut Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    pub fn apply(&self, output: &mut OutputJson) -> anyhow::Result<()> {
+        for processor in &self.processors {
+            processor.process(output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prepends an SPDX license identifier comment to each source file.
+pub struct LicenseStamp {
+    pub license: String,
+    pub language_map: LanguageMap,
+}
+
+impl OutputProcessor for LicenseStamp {
+    fn process(&self, output: &mut OutputJson) -> anyhow::Result<()> {
+        let lines = [format!("SPDX-License-Identifier: {}", self.license)];
+        for source_file in &mut output.source_files {
+            if !utils::prepend_comment(source_file, &lines, &self.language_map, None) {
+                info!(
+                    "Not adding the license to `{}`, unknown comment style",
+                    source_file.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds a `.gitignore` to the project root, replacing the one generated by the model.
+pub struct Gitignore {
+    pub contents: &'static str,
+}
+
+impl OutputProcessor for Gitignore {
+    fn process(&self, output: &mut OutputJson) -> anyhow::Result<()> {
+        output
+            .source_files
+            .retain(|source_file| source_file.name != ".gitignore");
+        output.source_files.push(SourceFile {
+            name: ".gitignore".to_string(),
+            contents: self.contents.to_string(),
+            origin: FileOrigin::Tool,
+        });
+        Ok(())
+    }
+}
+
+/// Path of the manifest added by `Manifest`.
+pub const MANIFEST_PATH: &str = ".project-pilot/manifest.json";
+
+#[derive(Debug, Serialize)]
+struct ManifestEntry<'a> {
+    name: &'a str,
+    bytes: usize,
+    lines: usize,
+    origin: FileOrigin,
+}
+
+/// Adds a manifest listing every file of the project with its size and origin, as JSON
+/// indented with `indent` spaces.
+pub struct Manifest {
+    pub indent: usize,
+}
+
+impl OutputProcessor for Manifest {
+    fn process(&self, output: &mut OutputJson) -> anyhow::Result<()> {
+        let files = utils::project_files(output);
+        let entries: Vec<ManifestEntry> = files
+            .iter()
+            .map(|file| ManifestEntry {
+                name: &file.name,
+                bytes: file.contents.len(),
+                lines: file.contents.lines().count(),
+                origin: file.origin,
+            })
+            .collect();
+        let contents = utils::to_json(&entries, self.indent)? + "\n";
+        output.source_files.push(SourceFile {
+            name: MANIFEST_PATH.to_string(),
+            contents,
+            origin: FileOrigin::Tool,
+        });
+        Ok(())
+    }
+}
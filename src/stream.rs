@@ -0,0 +1,161 @@
+use crate::utils::SourceFile;
+
+/// Incrementally scans a streamed JSON response and yields each element of the
+/// top level `source_files` array as soon as it has been fully received. Top level string
+/// fields, like `joke`, are collected as soon as they are complete as well.
+///
+/// The scanner only tracks the structure of the document (nesting depth, strings and
+/// the current top level key), so it never needs the whole response in memory at once
+/// beyond the buffer itself. When an element fails to decode the parser gives up and
+/// the caller is expected to fall back to a buffered parse of the complete response.
+#[derive(Debug, Default)]
+pub struct StreamParser {
+    buffer: String,
+    pos: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    string_start: usize,
+    last_string: String,
+    key: Option<String>,
+    value_pending: bool,
+    fields: Vec<(String, String)>,
+    in_source_files: bool,
+    element_start: Option<usize>,
+    failed: bool,
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The complete text received so far.
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// Whether incremental parsing was abandoned.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Take the top level string fields completed since the last call.
+    pub fn take_fields(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.fields)
+    }
+
+    /// Feed a chunk of the response and return the source files completed by it.
+    pub fn feed(&mut self, chunk: &str) -> Vec<SourceFile> {
+        self.buffer.push_str(chunk);
+        let mut completed = Vec::new();
+        if self.failed {
+            return completed;
+        }
+
+        let bytes = self.buffer.as_bytes();
+        while self.pos < bytes.len() {
+            let i = self.pos;
+            let c = bytes[i];
+            self.pos += 1;
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == b'\\' {
+                    self.escaped = true;
+                } else if c == b'"' {
+                    self.in_string = false;
+                    if self.depth == 1 && self.value_pending {
+                        self.value_pending = false;
+                        let value = serde_json::from_str(&self.buffer[self.string_start..=i]);
+                        if let (Some(key), Ok(value)) = (&self.key, value) {
+                            self.fields.push((key.clone(), value));
+                        }
+                    } else if self.depth == 1 {
+                        self.last_string = self.buffer[self.string_start + 1..i].to_string();
+                    }
+                }
+                continue;
+            }
+
+            match c {
+                b'"' => {
+                    self.in_string = true;
+                    self.string_start = i;
+                }
+                b':' if self.depth == 1 => {
+                    self.key = Some(self.last_string.clone());
+                    self.value_pending = true;
+                }
+                b',' if self.depth == 1 => self.value_pending = false,
+                b'{' | b'[' => {
+                    self.value_pending = false;
+                    self.depth += 1;
+                    if self.depth == 2 && c == b'[' && self.key.as_deref() == Some("source_files") {
+                        self.in_source_files = true;
+                    } else if self.depth == 3 && c == b'{' && self.in_source_files {
+                        self.element_start = Some(i);
+                    }
+                }
+                b'}' | b']' => {
+                    if self.depth == 3 && c == b'}' && self.in_source_files {
+                        if let Some(start) = self.element_start.take() {
+                            match serde_json::from_str::<SourceFile>(&self.buffer[start..=i]) {
+                                Ok(source_file) => completed.push(source_file),
+                                Err(_) => {
+                                    self.failed = true;
+                                    return completed;
+                                }
+                            }
+                        }
+                    }
+                    if self.depth == 0 {
+                        self.failed = true;
+                        return completed;
+                    }
+                    self.depth -= 1;
+                    if self.depth == 1 {
+                        self.in_source_files = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn feed_never_panics(
+            content in "[{}\\[\\]\":,a-z\\\\ \n]{0,200}|\\PC{0,100}",
+            splits in proptest::collection::vec(any::<proptest::sample::Index>(), 0..8),
+        ) {
+            // Feed the content in chunks, split on char boundaries.
+            let boundaries: Vec<usize> = content.char_indices().map(|(i, _)| i).collect();
+            let mut cuts: Vec<usize> = splits
+                .iter()
+                .filter(|_| !boundaries.is_empty())
+                .map(|split| boundaries[split.index(boundaries.len())])
+                .collect();
+            cuts.sort();
+            cuts.push(content.len());
+
+            let mut parser = StreamParser::new();
+            let mut start = 0;
+            for cut in cuts {
+                let _ = parser.feed(&content[start..cut]);
+                let _ = parser.take_fields();
+                start = cut;
+            }
+            prop_assert_eq!(parser.buffer(), content.as_str());
+        }
+    }
+}
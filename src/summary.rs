@@ -0,0 +1,85 @@
+use serde::Serialize;
+use std::fs;
+use std::time::Duration;
+
+use crate::{utils, Generation};
+
+/// A file written while generating a project.
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    pub path: String,
+    pub bytes: usize,
+}
+
+/// What is known about a project, filled in as it is generated so a failure still reports how
+/// far it got.
+#[derive(Debug, Default, Serialize)]
+pub struct ProjectSummary {
+    pub name: String,
+    pub path: Option<String>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_seconds: f64,
+    pub tokens: Option<u32>,
+    pub files: Vec<FileSummary>,
+    pub warnings: Vec<String>,
+}
+
+/// The run-level report written with --summary-json.
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_seconds: f64,
+    pub tokens: u32,
+    pub projects: Vec<ProjectSummary>,
+}
+
+impl RunSummary {
+    /// Record the outcome of generating one project along with the files written for it.
+    pub fn record(
+        &mut self,
+        mut project: ProjectSummary,
+        res: &anyhow::Result<Generation>,
+        elapsed: Duration,
+        writes: &[utils::FileWrite],
+    ) {
+        project.success = res.is_ok();
+        project.error = res.as_ref().err().map(|e| format!("{:#}", e));
+        project.duration_seconds = elapsed.as_secs_f64();
+        if let Ok(generation) = res {
+            project.tokens = generation.tokens;
+        }
+        project.files = writes
+            .iter()
+            .map(|write| FileSummary {
+                path: write.path.clone(),
+                bytes: write.bytes,
+            })
+            .collect();
+        project.files.sort_by(|a, b| a.path.cmp(&b.path));
+        self.tokens += project.tokens.unwrap_or(0);
+        self.projects.push(project);
+    }
+
+    /// Write the report of a run that ended with `res` to `summary_path` as JSON indented with
+    /// `indent` spaces.
+    pub fn write(
+        &mut self,
+        summary_path: &str,
+        res: &anyhow::Result<()>,
+        elapsed: Duration,
+        indent: usize,
+    ) -> anyhow::Result<()> {
+        self.success = res.is_ok();
+        self.error = res.as_ref().err().map(|e| format!("{:#}", e));
+        self.duration_seconds = elapsed.as_secs_f64();
+        fs::write(summary_path, utils::to_json(self, indent)? + "\n").map_err(|e| {
+            anyhow::anyhow!("Failed to write the summary `{}`: {}", summary_path, e)
+        })?;
+        info!("Wrote the run summary to `{}`", summary_path);
+        Ok(())
+    }
+}
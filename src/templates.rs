@@ -0,0 +1,178 @@
+use crate::utils::{FileOrigin, OutputJson, SourceFile};
+
+// The source files of each template, the Dockerfile, Makefile and README are read separately.
+// `{name}` is replaced with the project name and `{package}` with the package name. The name
+// isn't escaped so it is only used in the READMEs, code uses the package name.
+const RUST_CLI_FILES: [(&str, &str); 2] = [
+    (
+        "Cargo.toml",
+        include_str!("../templates/rust-cli/Cargo.toml"),
+    ),
+    (
+        "src/main.rs",
+        include_str!("../templates/rust-cli/src/main.rs"),
+    ),
+];
+
+const PYTHON_CLI_FILES: [(&str, &str); 3] = [
+    (
+        "tests/test_main.py",
+        include_str!("../templates/python-cli/tests/test_main.py"),
+    ),
+    (
+        "{package}/__init__.py",
+        include_str!("../templates/python-cli/{package}/__init__.py"),
+    ),
+    (
+        "{package}/__main__.py",
+        include_str!("../templates/python-cli/{package}/__main__.py"),
+    ),
+];
+
+const NODE_WEB_API_FILES: [(&str, &str); 4] = [
+    (
+        "package.json",
+        include_str!("../templates/node-web-api/package.json"),
+    ),
+    (
+        "src/app.js",
+        include_str!("../templates/node-web-api/src/app.js"),
+    ),
+    (
+        "src/index.js",
+        include_str!("../templates/node-web-api/src/index.js"),
+    ),
+    (
+        "test/app.test.js",
+        include_str!("../templates/node-web-api/test/app.test.js"),
+    ),
+];
+
+const GO_WEB_API_FILES: [(&str, &str); 3] = [
+    ("go.mod", include_str!("../templates/go-web-api/go.mod")),
+    ("main.go", include_str!("../templates/go-web-api/main.go")),
+    (
+        "main_test.go",
+        include_str!("../templates/go-web-api/main_test.go"),
+    ),
+];
+
+/// Built-in projects generated without the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Template {
+    RustCli,
+    PythonCli,
+    NodeWebApi,
+    GoWebApi,
+}
+
+impl Template {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Template::RustCli => "rust-cli",
+            Template::PythonCli => "python-cli",
+            Template::NodeWebApi => "node-web-api",
+            Template::GoWebApi => "go-web-api",
+        }
+    }
+
+    pub fn language(&self) -> &'static str {
+        match self {
+            Template::RustCli => "rust",
+            Template::PythonCli => "python",
+            Template::NodeWebApi => "javascript",
+            Template::GoWebApi => "go",
+        }
+    }
+
+    // The Dockerfile, Makefile and README, then the source files.
+    fn files(
+        &self,
+    ) -> (
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static [(&'static str, &'static str)],
+    ) {
+        macro_rules! template {
+            ($dir:literal, $files:expr) => {
+                (
+                    include_str!(concat!("../templates/", $dir, "/Dockerfile")),
+                    include_str!(concat!("../templates/", $dir, "/Makefile")),
+                    include_str!(concat!("../templates/", $dir, "/README.md")),
+                    &$files,
+                )
+            };
+        }
+        match self {
+            Template::RustCli => template!("rust-cli", RUST_CLI_FILES),
+            Template::PythonCli => template!("python-cli", PYTHON_CLI_FILES),
+            Template::NodeWebApi => template!("node-web-api", NODE_WEB_API_FILES),
+            Template::GoWebApi => template!("go-web-api", GO_WEB_API_FILES),
+        }
+    }
+
+    /// The project for `name`, with `package` as the package name. The Dockerfile and Makefile
+    /// are left out unless `docker` is set.
+    pub fn render(&self, name: &str, package: &str, docker: bool) -> OutputJson {
+        let substitute = |text: &str| text.replace("{name}", name).replace("{package}", package);
+        let (dockerfile, makefile, readme, source_files) = self.files();
+        OutputJson {
+            dockerfile: docker.then(|| substitute(dockerfile)),
+            makefile: docker.then(|| substitute(makefile)),
+            readme: substitute(readme),
+            source_files: source_files
+                .iter()
+                .map(|(file_name, contents)| SourceFile {
+                    name: substitute(file_name),
+                    contents: substitute(contents),
+                    origin: FileOrigin::Tool,
+                })
+                .collect(),
+            explanation: None,
+            joke: None,
+            origin: FileOrigin::Tool,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    const TEMPLATES: [Template; 4] = [
+        Template::RustCli,
+        Template::PythonCli,
+        Template::NodeWebApi,
+        Template::GoWebApi,
+    ];
+
+    #[test]
+    fn render_leaves_out_docker_files() {
+        for template in TEMPLATES {
+            let package = utils::package_name("demo", template.language());
+            let project = template.render("demo", &package, false);
+            assert!(project.dockerfile.is_none(), "{}", template.name());
+            assert!(project.makefile.is_none(), "{}", template.name());
+
+            let project = template.render("demo", &package, true);
+            assert!(project.dockerfile.is_some(), "{}", template.name());
+            assert!(project.makefile.is_some(), "{}", template.name());
+        }
+    }
+
+    #[test]
+    fn render_keeps_the_name_out_of_the_code() {
+        let name = "Say \"hi\" 100%";
+        for template in TEMPLATES {
+            let package = utils::package_name(name, template.language());
+            let project = template.render(name, &package, true);
+            assert!(project.readme.contains(name), "{}", template.name());
+            for file in &project.source_files {
+                assert!(!file.contents.contains(name), "{}", file.name);
+            }
+        }
+    }
+}
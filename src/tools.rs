@@ -0,0 +1,121 @@
+use async_openai::{
+    error::{ApiError, OpenAIError},
+    types::CreateChatCompletionRequest,
+    Client,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::utils::Completion;
+
+/// Name of the tool the model is asked to call with the generated project.
+pub const EMIT_PROJECT: &str = "emit_project";
+
+/// The `emit_project` tool definition, its parameters match `OutputJson`. The Dockerfile and
+/// Makefile are only required when `docker` is set.
+pub fn emit_project_tool(docker: bool) -> Value {
+    let required = if docker {
+        json!(["dockerfile", "makefile", "readme", "source_files"])
+    } else {
+        json!(["readme", "source_files"])
+    };
+    json!({
+        "type": "function",
+        "function": {
+            "name": EMIT_PROJECT,
+            "description": "Emit the files of the generated project.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "dockerfile": { "type": "string", "description": "Dockerfile contents" },
+                    "makefile": { "type": "string", "description": "Makefile contents" },
+                    "readme": { "type": "string", "description": "README contents" },
+                    "explanation": { "type": "string", "description": "Explanation of the architectural choices, only when asked for" },
+                    "joke": { "type": "string", "description": "A programming joke, only when asked for" },
+                    "source_files": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "name": { "type": "string", "description": "Path of the file relative to the project root" },
+                                "contents": { "type": "string", "description": "File contents" }
+                            },
+                            "required": ["name", "contents"]
+                        }
+                    }
+                },
+                "required": required
+            }
+        }
+    })
+}
+
+/// The error object OpenAI answers with when a request fails.
+#[derive(Debug, Deserialize)]
+struct WrappedError {
+    error: ApiError,
+}
+
+/// The body of `req` extended with the `emit_project` tool the model is forced to call.
+pub fn tool_request_body(req: &CreateChatCompletionRequest, docker: bool) -> anyhow::Result<Value> {
+    let mut body = serde_json::to_value(req)?;
+    body["tools"] = json!([emit_project_tool(docker)]);
+    body["tool_choice"] = json!({ "type": "function", "function": { "name": EMIT_PROJECT } });
+    Ok(body)
+}
+
+/// Send the request forcing the model to call `emit_project` and return the arguments of the
+/// call, which is the generated project as JSON.
+///
+/// async-openai doesn't support tool calling, so the request is serialized and extended with
+/// the tool definition before being sent with `http_client`. Failures are returned as the same
+/// `OpenAIError`s as async-openai, so that network and server errors are retried alike.
+pub async fn create_with_tool(
+    http_client: &reqwest::Client,
+    client: &Client,
+    req: &CreateChatCompletionRequest,
+    docker: bool,
+) -> anyhow::Result<Completion> {
+    let body = tool_request_body(req, docker)?;
+    let res = http_client
+        .post(format!("{}/chat/completions", client.api_base()))
+        .bearer_auth(client.api_key())
+        .json(&body)
+        .send()
+        .await
+        .map_err(OpenAIError::Reqwest)?;
+    let status = res.status();
+    let bytes = res.bytes().await.map_err(OpenAIError::Reqwest)?;
+    if !status.is_success() {
+        let wrapped: WrappedError =
+            serde_json::from_slice(&bytes).map_err(OpenAIError::JSONDeserialize)?;
+        return Err(OpenAIError::ApiError(wrapped.error).into());
+    }
+    let res: Value = serde_json::from_slice(&bytes).map_err(OpenAIError::JSONDeserialize)?;
+
+    let choice = &res["choices"][0];
+    let arguments = choice["message"]["tool_calls"]
+        .as_array()
+        .and_then(|calls| {
+            calls
+                .iter()
+                .find(|call| call["function"]["name"] == EMIT_PROJECT)
+        })
+        .and_then(|call| call["function"]["arguments"].as_str());
+    let Some(arguments) = arguments else {
+        // Without a tool call the message is the model explaining why it declined.
+        let message = &choice["message"];
+        match message["refusal"].as_str().or(message["content"].as_str()) {
+            Some(text) if !text.trim().is_empty() => {
+                anyhow::bail!("The model declined this request: {}", text.trim())
+            }
+            _ => anyhow::bail!("The model didn't call the `{}` tool", EMIT_PROJECT),
+        }
+    };
+
+    Ok(Completion {
+        content: arguments.to_string(),
+        finish_reason: choice["finish_reason"].as_str().map(String::from),
+        tokens: res["usage"]["total_tokens"].as_u64().map(|t| t as u32),
+    })
+}
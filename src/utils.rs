@@ -1,33 +1,769 @@
+use async_openai::types::{ChatCompletionRequestMessage, Role};
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::fs;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{BufWriter, Write};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::languages::LanguageMap;
+
+const WRITE_CHUNK_SIZE: usize = 8 * 1024;
+
+// How long each file written by `create_file` took, only recorded once profiling started.
+static WRITE_PROFILE: Mutex<Option<Vec<FileWrite>>> = Mutex::new(None);
+
+/// A file written by `create_file` and how long writing it took.
+#[derive(Debug, Clone)]
+pub struct FileWrite {
+    pub path: String,
+    pub bytes: usize,
+    pub duration: Duration,
+}
+
+/// Start recording how long each file write takes, dropping what was recorded before.
+pub fn start_write_profile() {
+    *WRITE_PROFILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Vec::new());
+}
+
+/// Stop recording the file writes and return them, the slowest first.
+pub fn take_write_profile() -> Vec<FileWrite> {
+    let mut writes = WRITE_PROFILE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .unwrap_or_default();
+    writes.sort_by_key(|write| std::cmp::Reverse(write.duration));
+    writes
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OutputJson {
-    pub dockerfile: String,
-    pub makefile: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dockerfile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub makefile: Option<String>,
+    #[serde(default)]
     pub readme: String,
+    #[serde(default)]
     pub source_files: Vec<SourceFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub joke: Option<String>,
+    /// Where the Dockerfile, Makefile and README come from.
+    #[serde(skip)]
+    pub origin: FileOrigin,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The fields of `OutputJson` that can be required from the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[value(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OutputField {
+    Dockerfile,
+    Makefile,
+    Readme,
+    SourceFiles,
+}
+
+impl OutputField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputField::Dockerfile => "dockerfile",
+            OutputField::Makefile => "makefile",
+            OutputField::Readme => "readme",
+            OutputField::SourceFiles => "source_files",
+        }
+    }
+
+    /// Whether the field is missing or empty in `output`.
+    fn is_empty(&self, output: &OutputJson) -> bool {
+        let text = match self {
+            OutputField::Dockerfile => output.dockerfile.as_deref(),
+            OutputField::Makefile => output.makefile.as_deref(),
+            OutputField::Readme => Some(output.readme.as_str()),
+            OutputField::SourceFiles => return output.source_files.is_empty(),
+        };
+        text.is_none_or(|text| text.trim().is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SourceFile {
     pub name: String,
     pub contents: String,
+    #[serde(skip)]
+    pub origin: FileOrigin,
 }
 
-pub fn generate_prompt(name: &str, description: &str, language: &str) -> String {
-    format!(
-        "Take the following programming language, application requirements, and produce a working application.
+/// Where a file of the project comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileOrigin {
+    /// Generated by the model, to be reviewed.
+    #[default]
+    Model,
+    /// Added by project-pilot itself, or from a template.
+    Tool,
+}
 
-        your solution must include:
-        1. Dockerfile that allows the application to be built and run
+/// The messages of a conversation as text, each after a line with the role, like
+/// `System:`.
+pub fn format_messages(messages: &[ChatCompletionRequestMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            let role = match message.role {
+                Role::System => "System",
+                Role::User => "User",
+                Role::Assistant => "Assistant",
+            };
+            format!("{}:\n{}\n", role, message.content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub const SYSTEM_PROMPT: &str = "You are a helpful programming assistant.
+                    You are expected to process an application description and generate the files and steps necessary to create the application using your language model.
+                    You can only respond with a Json object that matches the provided output schema.
+                    The returned Json can include an array of objects as defined by the output schema.
+                    You are not allowed to return anything but a valid Json object.";
+
+pub const VALIDATION_SYSTEM_PROMPT: &str = "You are a helpful programming assistant.
+You are expected to review the files of an application and decide whether the application satisfies its requirements.
+You can only respond with a Json object that matches the provided output schema.
+You are not allowed to return anything but a valid Json object.";
+
+/// The text of a model response along with its metadata.
+#[derive(Debug)]
+pub struct Completion {
+    pub content: String,
+    pub finish_reason: Option<String>,
+    pub tokens: Option<u32>,
+}
+
+const CLI_PRESET: &str = "The application is a command line tool. Parse the command line arguments with the idiomatic argument parsing library for the language, include a --help output and exit with a non-zero status code on errors.";
+const WEB_API_PRESET: &str = "The application is a web API. Use the idiomatic web framework for the language, organize the endpoints with a router, include a GET /health endpoint that returns a 200 status and read the port to listen on from the PORT environment variable.";
+const LIBRARY_PRESET: &str = "The application is a library. Expose a small public API with documentation comments, include unit tests for the public API and a short usage example in the readme.";
+const WORKER_PRESET: &str = "The application is a background worker. Run a processing loop that handles jobs one at a time, log the progress of each job and shut down gracefully when receiving SIGTERM.";
+
+/// Project archetypes with specific scaffolding instructions for the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Cli,
+    WebApi,
+    Library,
+    Worker,
+}
+
+impl Preset {
+    pub fn instructions(&self) -> &'static str {
+        match self {
+            Preset::Cli => CLI_PRESET,
+            Preset::WebApi => WEB_API_PRESET,
+            Preset::Library => LIBRARY_PRESET,
+            Preset::Worker => WORKER_PRESET,
+        }
+    }
+}
+
+/// How the project directory name is derived from the project name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NameScheme {
+    #[default]
+    Plain,
+    /// Suffixed with the local time, like `myapp-20240115-143022`.
+    Timestamped,
+    /// Suffixed with 8 random hex digits.
+    Random,
+}
+
+impl NameScheme {
+    pub fn dir_name(&self, name: &str) -> String {
+        match self {
+            NameScheme::Plain => name.to_string(),
+            NameScheme::Timestamped => {
+                format!("{}-{}", name, chrono::Local::now().format("%Y%m%d-%H%M%S"))
+            }
+            NameScheme::Random => {
+                let random = std::collections::hash_map::RandomState::new()
+                    .build_hasher()
+                    .finish();
+                format!("{}-{:08x}", name, random as u32)
+            }
+        }
+    }
+}
+
+/// What to do with a response that can't be decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParseFailure {
+    /// Fail, unless --max-retries-on-parse allows asking again.
+    #[default]
+    Abort,
+    /// Save the response, then behave like `abort`.
+    Save,
+    /// Save the response and ask again, at least once.
+    SaveAndRetry,
+}
+
+impl ParseFailure {
+    pub fn saves(&self) -> bool {
+        *self != ParseFailure::Abort
+    }
+}
+
+/// Save a response that failed to decode in `dir`, in a file named after the project and the
+/// local time. Returns the path of the file.
+pub fn save_failed_response(dir: &str, name: &str, content: &str) -> anyhow::Result<String> {
+    fs::create_dir_all(dir)?;
+    let path = format!(
+        "{}/{}-failed-response-{}.txt",
+        dir.trim_end_matches('/'),
+        name,
+        chrono::Local::now().format("%Y%m%d-%H%M%S-%3f")
+    );
+    create_file(&path, content)?;
+    Ok(path)
+}
+
+/// Which variant to write without asking when several are requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutoChoose {
+    First,
+}
+
+// Tokens (from `tiktoken`) that start a markdown code fence: "```", " ```" and "``".
+const CL100K_FENCE_TOKENS: [u32; 3] = [74694, 55375, 14196];
+const O200K_FENCE_TOKENS: [u32; 3] = [168394, 101822, 26178];
+
+/// A `logit_bias` that bans the tokens starting markdown code fences for the tokenizer of
+/// `model`. Token ids differ per tokenizer, so `None` is returned for unknown models.
+/// Note that this also keeps the model from using code blocks within the generated README.
+pub fn fence_logit_bias(model: &str) -> Option<HashMap<String, serde_json::Value>> {
+    let model = base_model(model);
+    let tokens = if ["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"]
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+    {
+        O200K_FENCE_TOKENS
+    } else if model.starts_with("gpt-4") || model.starts_with("gpt-3.5") {
+        CL100K_FENCE_TOKENS
+    } else {
+        return None;
+    };
+
+    Some(
+        tokens
+            .iter()
+            .map(|token| (token.to_string(), serde_json::json!(-100)))
+            .collect(),
+    )
+}
+
+// Short names for models, extended and overridden by the `[aliases]` of --model-aliases.
+const MODEL_ALIASES: [(&str, &str); 3] = [
+    ("fast", "gpt-4o-mini"),
+    ("smart", "gpt-4o"),
+    ("cheap", "gpt-3.5-turbo"),
+];
+
+#[derive(Debug, Default, Deserialize)]
+struct ModelAliasesConfig {
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+}
+
+/// Resolve `model` when it is an alias, from the TOML file at `aliases_path` first and the
+/// built-in aliases otherwise. Other names are returned as is.
+pub fn resolve_model_alias(model: &str, aliases_path: Option<&str>) -> anyhow::Result<String> {
+    if let Some(aliases_path) = aliases_path {
+        let contents = fs::read_to_string(aliases_path).map_err(|e| {
+            anyhow::anyhow!("Failed to read model aliases `{}`: {}", aliases_path, e)
+        })?;
+        let config: ModelAliasesConfig = toml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid model aliases `{}`: {}", aliases_path, e))?;
+        if let Some(resolved) = config.aliases.get(model) {
+            return Ok(resolved.clone());
+        }
+    }
+    Ok(MODEL_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == model)
+        .map_or(model, |(_, resolved)| resolved)
+        .to_string())
+}
+
+/// The model a fine-tuned model, like `ft:gpt-3.5-turbo:org:name:id`, is based on.
+pub fn fine_tuned_base(model: &str) -> Option<&str> {
+    model
+        .strip_prefix("ft:")
+        .map(|rest| rest.split(':').next().unwrap_or(rest))
+        .filter(|base| !base.is_empty())
+}
+
+// Fine-tuned models share the limits and tokenizer of their base model.
+fn base_model(model: &str) -> &str {
+    fine_tuned_base(model).unwrap_or(model)
+}
+
+// Max output tokens per model prefix, more specific prefixes first.
+const MAX_OUTPUT_TOKENS: [(&str, u32); 16] = [
+    ("gpt-3.5-turbo", 4096),
+    ("gpt-4o-mini", 16384),
+    ("gpt-4o", 16384),
+    ("gpt-4.1", 32768),
+    ("gpt-4-turbo", 4096),
+    ("gpt-4-32k", 8192),
+    ("gpt-4-1106", 4096),
+    ("gpt-4-0125", 4096),
+    ("gpt-4-vision-preview", 4096),
+    ("gpt-4", 8192),
+    ("gpt-5", 128000),
+    ("o1-preview", 32768),
+    ("o1-mini", 65536),
+    ("o1", 100000),
+    ("o3", 100000),
+    ("o4-mini", 100000),
+];
+
+/// The maximum number of output tokens `model` supports, if known.
+pub fn max_output_tokens(model: &str) -> Option<u32> {
+    MAX_OUTPUT_TOKENS
+        .iter()
+        .find(|(prefix, _)| base_model(model).starts_with(prefix))
+        .map(|(_, max)| *max)
+}
+
+// Prices in USD per million input and output tokens, by model prefix, the more specific
+// prefixes first.
+const MODEL_PRICES: [(&str, f64, f64); 19] = [
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4-1106", 10.0, 30.0),
+    ("gpt-4-0125", 10.0, 30.0),
+    ("gpt-4-vision-preview", 10.0, 30.0),
+    ("gpt-4-32k", 60.0, 120.0),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-5-mini", 0.25, 2.0),
+    ("gpt-5-nano", 0.05, 0.4),
+    ("gpt-5", 1.25, 10.0),
+    ("o1-mini", 1.1, 4.4),
+    ("o1", 15.0, 60.0),
+    ("o3-mini", 1.1, 4.4),
+    ("o3", 2.0, 8.0),
+];
+
+/// The price of `model` in USD per million input and output tokens, if known. Fine-tuned
+/// models get the price of their base model, which is usually lower.
+pub fn model_price(model: &str) -> Option<(f64, f64)> {
+    MODEL_PRICES
+        .iter()
+        .find(|(prefix, _, _)| base_model(model).starts_with(prefix))
+        .map(|(_, input, output)| (*input, *output))
+}
+
+/// A rough count of the tokens of `text`, about 4 characters per token for English and code.
+pub fn estimate_tokens(text: &str) -> u32 {
+    u32::try_from(text.chars().count().div_ceil(4)).unwrap_or(u32::MAX)
+}
+
+/// Warnings collected during a run, printed as they happen and summarized at the end.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    messages: Vec<String>,
+}
+
+impl Warnings {
+    pub fn warn(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        info!("Warning: {}", message);
+        self.messages.push(message);
+    }
+
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    pub fn print_summary(&self) {
+        if self.messages.is_empty() {
+            return;
+        }
+        info!("{} warning(s) occurred:", self.messages.len());
+        for message in &self.messages {
+            info!("- {}", message);
+        }
+        info!();
+    }
+
+    /// Fail when warnings occurred and `strict` is set.
+    pub fn check(&self, strict: bool) -> anyhow::Result<()> {
+        if strict && !self.messages.is_empty() {
+            anyhow::bail!(
+                "{} warning(s) treated as errors because of --strict",
+                self.messages.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Substitute `${VAR}` references with the value of the environment variable `VAR`.
+pub fn expand_env(text: &str) -> anyhow::Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow::anyhow!("Unterminated `${{` in `{}`", text))?;
+        let name = &after[..end];
+        let value = std::env::var(name)
+            .map_err(|_| anyhow::anyhow!("Environment variable `{}` is not defined", name))?;
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Concatenate the files matching the glob `pattern`, in sorted order, each under a header with
+/// its file name.
+pub fn read_spec_files(pattern: &str) -> anyhow::Result<String> {
+    let mut paths = glob::glob(pattern)
+        .map_err(|e| anyhow::anyhow!("Invalid glob pattern `{}`: {}", pattern, e))?
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.is_file());
+    if paths.is_empty() {
+        anyhow::bail!("No spec files match `{}`", pattern);
+    }
+    paths.sort();
+
+    let mut specs = Vec::new();
+    for path in paths {
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read `{}`: {}", path.display(), e))?;
+        specs.push(format!("--- {} ---\n{}", path.display(), contents.trim()));
+    }
+    Ok(specs.join("\n\n"))
+}
+
+/// Detect the programming language of an existing file from its shebang or extension.
+/// Fails when the language can't be determined or when several languages are plausible.
+pub fn detect_language(file_path: &str) -> anyhow::Result<String> {
+    let contents = fs::read_to_string(file_path).unwrap_or_default();
+    let candidates: &[&str] = match contents.lines().next().and_then(|l| l.strip_prefix("#!")) {
+        Some(shebang) => {
+            // `#!/usr/bin/env python3` and `#!/usr/bin/python3` both name the interpreter last.
+            let interpreter = shebang
+                .split_whitespace()
+                .rfind(|part| !part.starts_with('-'))
+                .and_then(|part| part.rsplit('/').next())
+                .unwrap_or_default();
+            match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+                "python" => &["python"],
+                "node" => &["javascript"],
+                "deno" | "ts-node" => &["typescript"],
+                "bash" | "sh" | "zsh" => &["bash"],
+                "ruby" => &["ruby"],
+                "perl" => &["perl"],
+                "php" => &["php"],
+                "lua" => &["lua"],
+                _ => &[],
+            }
+        }
+        None => &[],
+    };
+
+    let candidates = if candidates.is_empty() {
+        let extension = Path::new(file_path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "rs" => &["rust"][..],
+            "py" => &["python"],
+            "js" | "mjs" | "cjs" | "jsx" => &["javascript"],
+            "ts" | "tsx" => &["typescript"],
+            "go" => &["go"],
+            "rb" => &["ruby"],
+            "java" => &["java"],
+            "kt" | "kts" => &["kotlin"],
+            "scala" => &["scala"],
+            "swift" => &["swift"],
+            "c" => &["c"],
+            "cc" | "cpp" | "cxx" | "hpp" => &["c++"],
+            "h" => &["c", "c++", "objective-c"],
+            "m" => &["objective-c", "matlab"],
+            "cs" => &["c#"],
+            "php" => &["php"],
+            "sh" | "bash" => &["bash"],
+            "pl" => &["perl", "prolog"],
+            "ex" | "exs" => &["elixir"],
+            "erl" => &["erlang"],
+            "hs" => &["haskell"],
+            "lua" => &["lua"],
+            "dart" => &["dart"],
+            "r" => &["r"],
+            "jl" => &["julia"],
+            "zig" => &["zig"],
+            _ => &[],
+        }
+    } else {
+        candidates
+    };
+
+    match candidates {
+        [] => anyhow::bail!("Could not detect the language of `{}`", file_path),
+        [language] => Ok(language.to_string()),
+        _ => anyhow::bail!(
+            "The language of `{}` is ambiguous, it could be any of: {}. Use --language instead",
+            file_path,
+            candidates.join(", ")
+        ),
+    }
+}
+
+/// Derive a valid package identifier from a human readable project name, snake_case for
+/// languages that need identifiers like rust and python, kebab-case otherwise like npm.
+pub fn package_name(name: &str, language: &str) -> String {
+    let language = language.to_lowercase();
+    let separator = if ["rust", "python"].contains(&language.as_str()) {
+        "_"
+    } else {
+        "-"
+    };
+
+    // Split on anything that isn't alphanumeric and on camelCase boundaries.
+    let mut words: Vec<String> = Vec::new();
+    let mut previous_lowercase = false;
+    for c in name.chars() {
+        if !c.is_ascii_alphanumeric() {
+            words.push(String::new());
+            previous_lowercase = false;
+            continue;
+        }
+        if c.is_ascii_uppercase() && previous_lowercase {
+            words.push(String::new());
+        }
+        match words.last_mut() {
+            Some(word) => word.push(c.to_ascii_lowercase()),
+            None => words.push(c.to_ascii_lowercase().to_string()),
+        }
+        previous_lowercase = c.is_ascii_lowercase() || c.is_ascii_digit();
+    }
+    words.retain(|word| !word.is_empty());
+
+    let package = words.join(separator);
+    if package.is_empty() {
+        "app".to_string()
+    } else if package.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("app{}{}", separator, package)
+    } else {
+        package
+    }
+}
+
+// Prefix of OpenAI API keys, redacted when followed by at least `API_KEY_MIN_LEN` key chars.
+const API_KEY_PREFIX: &str = "sk-";
+const API_KEY_MIN_LEN: usize = 20;
+
+/// Replaces secrets with `***` in the prompts and responses that are printed or logged: the
+/// given patterns, the API key in the environment and anything that looks like an API key.
+#[derive(Debug, Default, Clone)]
+pub struct Redactor {
+    patterns: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String]) -> Self {
+        // A short key is no real key, redacting it would mangle everything else.
+        let api_key = std::env::var("OPENAI_API_KEY")
+            .ok()
+            .filter(|key| key.trim().len() >= API_KEY_MIN_LEN);
+        let mut patterns: Vec<String> = patterns
+            .iter()
+            .filter(|pattern| !pattern.is_empty())
+            .cloned()
+            .chain(api_key)
+            .collect();
+        // Longer patterns first, so that a pattern containing another one is fully redacted.
+        patterns.sort_by_key(|pattern| std::cmp::Reverse(pattern.len()));
+        Redactor { patterns }
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+        for pattern in &self.patterns {
+            redacted = redacted.replace(pattern.as_str(), "***");
+        }
+        redact_api_keys(&redacted)
+    }
+
+    /// Redact every string of a JSON value, keys included.
+    pub fn redact_json(&self, value: &serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::String(s) => serde_json::Value::String(self.redact(s)),
+            serde_json::Value::Array(values) => {
+                values.iter().map(|value| self.redact_json(value)).collect()
+            }
+            serde_json::Value::Object(map) => map
+                .iter()
+                .map(|(key, value)| (self.redact(key), self.redact_json(value)))
+                .collect(),
+            value => value.clone(),
+        }
+    }
+}
+
+fn is_api_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+// Replace the words starting with `API_KEY_PREFIX` that are long enough to be API keys.
+fn redact_api_keys(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(API_KEY_PREFIX) {
+        let at_word_start = !rest[..start].ends_with(is_api_key_char);
+        let key = &rest[start + API_KEY_PREFIX.len()..];
+        let key_len = key.find(|c| !is_api_key_char(c)).unwrap_or(key.len());
+        let end = start + API_KEY_PREFIX.len() + key_len;
+        redacted.push_str(&rest[..start]);
+        if at_word_start && key_len >= API_KEY_MIN_LEN {
+            redacted.push_str("***");
+        } else {
+            redacted.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// The words joined into a command line, quoting the words the shell would interpret.
+pub fn shell_command(words: impl IntoIterator<Item = String>) -> String {
+    words
+        .into_iter()
+        .map(|word| {
+            let plain = !word.is_empty()
+                && word
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+            if plain {
+                word
+            } else {
+                format!("'{}'", word.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Serialize `value` as JSON indented with `indent` spaces, on a single line for 0.
+pub fn to_json<T: Serialize + ?Sized>(value: &T, indent: usize) -> serde_json::Result<String> {
+    if indent == 0 {
+        return serde_json::to_string(value);
+    }
+    let indent = " ".repeat(indent);
+    let mut json = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    value.serialize(&mut serde_json::Serializer::with_formatter(
+        &mut json, formatter,
+    ))?;
+    Ok(String::from_utf8(json).expect("serde_json writes UTF-8"))
+}
+
+/// A `curl` command sending `body` and the extra `headers` to the chat completions endpoint of
+/// `api_base`, with the API key left as a reference to the environment variable.
+pub fn curl_command(
+    api_base: &str,
+    body: &serde_json::Value,
+    headers: &[(String, String)],
+    indent: usize,
+) -> anyhow::Result<String> {
+    // Single quotes keep the shell from interpreting anything but a single quote itself.
+    let quote = |s: &str| s.replace('\'', "'\\''");
+    let body = quote(&to_json(body, indent)?);
+    let headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("  -H '{}: {}' \\\n", quote(name), quote(value)))
+        .collect();
+    Ok(format!(
+        "curl {}/chat/completions \\\n  -H 'Content-Type: application/json' \\\n  -H \"Authorization: Bearer $OPENAI_API_KEY\" \\\n{}  -d '{}'",
+        api_base, headers, body
+    ))
+}
+
+pub fn generate_prompt(
+    name: &str,
+    description: &str,
+    language: &str,
+    docker: bool,
+    requirements: &[String],
+) -> String {
+    let requirements = if requirements.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "Additional Requirements:
+        ---
+{}        ---
+
+        ",
+            requirements
+                .iter()
+                .map(|r| format!("        - {}\n", r))
+                .collect::<String>()
+        )
+    };
+
+    let (solution, docker_schema) = if docker {
+        (
+            "1. Dockerfile that allows the application to be built and run
         2. Makefile that contains the following commands assuming that the application is executed using the Dockerfile.
             a. make build
             b. make run (make sure that docker cleans up after itself)
             c. make test (make sure that docker cleans up after itself)
         3. Readme with instructions required to build and run the application
-        4. files with the source code for the application, make sure to not escape the control characters twice, like \\n because that will break the source code.
+        4. files with the source code for the application, make sure to not escape the control characters twice, like \\n because that will break the source code.",
+            "\"dockerfile\": \"dockerfile contents\",
+            \"makefile\": \"makefile contents\",
+            ",
+        )
+    } else {
+        (
+            "1. Readme with instructions required to build and run the application without docker
+        2. files with the source code for the application, make sure to not escape the control characters twice, like \\n because that will break the source code.",
+            "",
+        )
+    };
+
+    format!(
+        "Take the following programming language, application requirements, and produce a working application.
+
+        your solution must include:
+        {solution}
 
 
         The output must match the provided output json schema and be a valid json.
@@ -47,11 +783,9 @@ pub fn generate_prompt(name: &str, description: &str, language: &str) -> String
         {description}
         ---
 
-        Output Json schema:
+        {requirements}Output Json schema:
         {{
-            \"dockerfile\": \"dockerfile contents\",
-            \"makefile\": \"makefile contents\",
-            \"readme\": \"readme contents\",
+            {docker_schema}\"readme\": \"readme contents\",
             \"source_files\": [
                 {{
                     \"name\": \"...\",
@@ -65,41 +799,1326 @@ pub fn generate_prompt(name: &str, description: &str, language: &str) -> String
         Respond ONLY with the data portion of a valid Json object. No schema definition required. No other words.",
         name = name,
         description = description,
-        language = language
+        language = language,
+        solution = solution,
+        docker_schema = docker_schema,
+        requirements = requirements
     )
 }
 
-pub fn create_file(file_path: &str, file_contents: &str) -> anyhow::Result<()> {
-    println!("Creating file `{}`", file_path);
-    fs::write(file_path, file_contents)?;
-    Ok(())
+// Sections the README must cover, with the heading keywords and commands that cover them.
+const README_SECTIONS: [(&str, &[&str], &[&str]); 2] = [
+    (
+        "build",
+        &["build", "install", "setup", "getting started"],
+        &["make build", "docker build"],
+    ),
+    (
+        "run",
+        &["run", "usage", "start", "getting started"],
+        &["make run", "docker run"],
+    ),
+];
+
+/// Whether the project has no source files and no Dockerfile or Makefile contents, leaving
+/// only a README at best.
+pub fn is_empty_project(output: &OutputJson) -> bool {
+    let is_blank = |contents: &Option<String>| contents.as_deref().unwrap_or("").trim().is_empty();
+    output.source_files.is_empty() && is_blank(&output.dockerfile) && is_blank(&output.makefile)
+}
+
+/// Return the sections the README should have, build and run instructions, that aren't covered
+/// by a heading or by the commands themselves.
+pub fn missing_readme_sections(readme: &str) -> Vec<&'static str> {
+    let readme = readme.to_lowercase();
+    let headings: Vec<&str> = readme
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+        .collect();
+
+    README_SECTIONS
+        .iter()
+        .filter(|(_, keywords, commands)| {
+            !headings
+                .iter()
+                .any(|heading| keywords.iter().any(|keyword| heading.contains(keyword)))
+                && !commands.iter().any(|command| readme.contains(command))
+        })
+        .map(|(section, _, _)| *section)
+        .collect()
+}
+
+/// Return the targets from `targets` that aren't defined in the makefile.
+pub fn missing_make_targets(makefile: &str, targets: &[&str]) -> Vec<String> {
+    let defined: Vec<&str> = makefile
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| {
+            let (names, rest) = line.split_once(':')?;
+            // `name := value` and `name ::= value` are variable assignments, not rules.
+            if rest.starts_with('=') || rest.starts_with(":=") {
+                return None;
+            }
+            Some(names)
+        })
+        .flat_map(str::split_whitespace)
+        .collect();
+
+    targets
+        .iter()
+        .filter(|target| !defined.contains(target))
+        .map(|target| target.to_string())
+        .collect()
+}
+
+// Phrases models open refusals with.
+const REFUSAL_PHRASES: [&str; 8] = [
+    "i'm sorry",
+    "i am sorry",
+    "i apologize",
+    "i can't",
+    "i cannot",
+    "i'm unable",
+    "i am unable",
+    "as an ai",
+];
+
+/// Whether a response that failed to decode is likely the model declining the request: prose
+/// without any JSON object, or opening with an apology.
+pub fn is_refusal(content: &str) -> bool {
+    let opening = content.trim_start().to_lowercase().replace('\u{2019}', "'");
+    !content.contains('{')
+        || REFUSAL_PHRASES
+            .iter()
+            .any(|phrase| opening.starts_with(phrase))
+}
+
+/// Decode the project from the response, using the JSON Pointer `json_path` to find it.
+/// The pointed to value may also be a string containing the project as JSON. Fails when one
+/// of the `required` fields is missing or empty, the Dockerfile and Makefile are dropped unless
+/// `docker` is set.
+pub fn decode_output(
+    content: &str,
+    json_path: &str,
+    docker: bool,
+    required: &[OutputField],
+) -> anyhow::Result<OutputJson> {
+    let mut output = decode_output_json(content, json_path)?;
+    if !docker {
+        output.dockerfile = None;
+        output.makefile = None;
+    }
+
+    let empty: Vec<&str> = required
+        .iter()
+        .filter(|field| field.is_empty(&output))
+        .map(|field| field.name())
+        .collect();
+    if !empty.is_empty() {
+        anyhow::bail!(
+            "The response is missing or has empty required fields: {}",
+            empty.join(", ")
+        );
+    }
+    Ok(output)
+}
+
+fn decode_output_json(content: &str, json_path: &str) -> anyhow::Result<OutputJson> {
+    if json_path.is_empty() {
+        return Ok(serde_json::from_str(content)?);
+    }
+    if !json_path.starts_with('/') {
+        anyhow::bail!(
+            "Invalid JSON Pointer `{}`, it must start with `/`",
+            json_path
+        );
+    }
+
+    let mut value: serde_json::Value = serde_json::from_str(content)?;
+    let payload = value
+        .pointer_mut(json_path)
+        .ok_or_else(|| anyhow::anyhow!("Nothing found at `{}` in the response", json_path))?
+        .take();
+    match payload {
+        serde_json::Value::String(json) => Ok(serde_json::from_str(&json)?),
+        payload => Ok(serde_json::from_value(payload)?),
+    }
+}
+
+pub fn generate_validation_prompt(description: &str, files: &[SourceFile]) -> String {
+    let files: String = files
+        .iter()
+        .map(|file| format!("File `{}`:\n---\n{}\n---\n\n", file.name, file.contents))
+        .collect();
+
+    format!(
+        "Review the following application and decide whether it satisfies the application requirements.
+The application passes when it implements every requirement and can be built and run, list every requirement that is missing or incomplete as a gap.
+
+Application Requirements:
+---
+{description}
+---
+
+Application Files:
+
+{files}Output Json schema:
+{{
+    \"pass\": true,
+    \"gaps\": [\"...\"]
+}}
+
+Respond ONLY with the data portion of a valid Json object. No schema definition required. No other words.",
+        description = description,
+        files = files
+    )
+}
+
+/// Append a continuation of a truncated response, dropping any code fence the model opened the
+/// continuation with and any text it repeated from the end of the previous part.
+pub fn merge_continuation(previous: &str, next: &str) -> String {
+    if previous.is_empty() {
+        return next.to_string();
+    }
+
+    let mut next = next;
+    for fence in ["```json\n", "```\n"] {
+        if let Some(stripped) = next.strip_prefix(fence) {
+            next = stripped;
+            break;
+        }
+    }
+
+    // Short overlaps are too likely to be a coincidence, e.g. a single quote.
+    const MIN_OVERLAP: usize = 8;
+    const MAX_OVERLAP: usize = 500;
+    let overlap = next
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .take_while(|&i| i <= MAX_OVERLAP)
+        .filter(|&i| i >= MIN_OVERLAP && previous.ends_with(&next[..i]))
+        .last()
+        .unwrap_or(0);
+
+    format!("{}{}", previous, &next[overlap..])
+}
+
+// Environment variables that are always set and never need to be provided by the user.
+const COMMON_ENV_VARS: [&str; 7] = ["PATH", "HOME", "PWD", "USER", "SHELL", "TERM", "HOSTNAME"];
+
+/// Best-effort list of manual steps the user still has to take before the project runs,
+/// found by scanning the README and Dockerfile for environment variables and TODO markers.
+pub fn follow_up_steps(output: &OutputJson) -> Vec<String> {
+    let mut steps: Vec<String> = Vec::new();
+    let mut push = |step: String| {
+        if !steps.contains(&step) {
+            steps.push(step);
+        }
+    };
+
+    for (file, text) in [
+        ("README.md", Some(&output.readme)),
+        ("Dockerfile", output.dockerfile.as_ref()),
+    ]
+    .into_iter()
+    .filter_map(|(file, text)| Some((file, text?)))
+    {
+        for line in text.lines() {
+            let trimmed = line.trim();
+            if let Some(arg) = trimmed.strip_prefix("ARG ") {
+                if !arg.contains('=') {
+                    push(format!(
+                        "provide the `{}` docker build argument",
+                        arg.trim()
+                    ));
+                }
+            }
+            if let Some(var) = trimmed
+                .strip_prefix("export ")
+                .and_then(|rest| rest.split_once('='))
+                .map(|(var, _)| var.trim())
+            {
+                if is_env_var_name(var) {
+                    push(format!("set {}", var));
+                }
+            }
+            for var in env_var_references(line) {
+                push(format!("set {}", var));
+            }
+
+            let upper = line.to_uppercase();
+            if upper.contains("TODO") || upper.contains("FIXME") || upper.contains("<YOUR") {
+                push(format!("resolve `{}` in {}", trimmed, file));
+            }
+        }
+    }
+    if output.readme.contains(".env") {
+        push("create the .env file described in the README".to_string());
+    }
+
+    steps
+}
+
+fn is_env_var_name(name: &str) -> bool {
+    name.len() > 1
+        && name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        && !COMMON_ENV_VARS.contains(&name)
+}
+
+// Environment variables referenced as `$VAR` or `${VAR}`.
+fn env_var_references(line: &str) -> Vec<&str> {
+    line.match_indices('$')
+        .filter_map(|(i, _)| {
+            let rest = line[i + 1..].trim_start_matches('{');
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            Some(&rest[..end]).filter(|name| is_env_var_name(name))
+        })
+        .collect()
+}
+
+/// Name of the Dockerfile for local development.
+pub const DEV_DOCKERFILE: &str = "Dockerfile.dev";
+
+/// Whether a source file collides with the Dockerfile, Makefile or README written separately.
+/// The development `Dockerfile.dev` is a source file of its own.
+pub fn is_reserved_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    if name == DEV_DOCKERFILE.to_lowercase() {
+        return false;
+    }
+    name.contains("makefile") || name.contains("dockerfile") || name.contains("readme")
+}
+
+/// Drop the source files that collide with the Dockerfile, Makefile or README, with a warning.
+/// The writers skip them as well.
+pub fn skip_reserved_files(source_files: &mut Vec<SourceFile>, warnings: &mut Warnings) {
+    source_files.retain(|source_file| {
+        let reserved = is_reserved_name(&source_file.name);
+        if reserved {
+            warnings.warn(format!(
+                "Skipping source file `{}` because the Dockerfile, Makefile and README are written separately",
+                source_file.name
+            ));
+        }
+        !reserved
+    });
+}
+
+/// The first `max_lines` lines of `text`, or all of them when `None`, followed by a line
+/// telling how many lines were left out.
+pub fn preview(text: &str, max_lines: Option<usize>) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let shown = max_lines.unwrap_or(lines.len()).min(lines.len());
+    let mut preview: String = lines[..shown]
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect();
+    if shown < lines.len() {
+        preview.push_str(&format!("... ({} more lines)\n", lines.len() - shown));
+    }
+    preview
+}
+
+// Share of non-printable characters above which contents look binary.
+const MAX_NON_PRINTABLE_RATIO: f64 = 0.1;
+// Lines of only base64 characters at least this long look like encoded binary data.
+const MIN_BASE64_LINE: usize = 512;
+
+/// Why `contents` looks like binary or encoded data rather than source code, if it does: null
+/// bytes, many non-printable characters or long base64 lines.
+pub fn binary_content(contents: &str) -> Option<&'static str> {
+    if contents.contains('\0') {
+        return Some("contains null bytes");
+    }
+    let total = contents.chars().count();
+    let non_printable = contents
+        .chars()
+        .filter(|&c| (c.is_control() && !c.is_whitespace()) || c == char::REPLACEMENT_CHARACTER)
+        .count();
+    if total > 0 && non_printable as f64 / total as f64 > MAX_NON_PRINTABLE_RATIO {
+        return Some("contains many non-printable characters");
+    }
+    let base64_line = contents.lines().any(|line| {
+        line.len() >= MIN_BASE64_LINE
+            && line
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/' || b == b'=')
+    });
+    if base64_line {
+        return Some("looks like base64 encoded data");
+    }
+    None
 }
 
-pub fn create_source_files(
+/// All files of the project relative to the project root, in the order they are written.
+pub fn project_files(output: &OutputJson) -> Vec<SourceFile> {
+    let mut files: Vec<SourceFile> = [
+        ("Dockerfile", &output.dockerfile),
+        ("Makefile", &output.makefile),
+    ]
+    .into_iter()
+    .filter_map(|(name, contents)| {
+        Some(SourceFile {
+            name: name.to_string(),
+            contents: contents.clone()?,
+            origin: output.origin,
+        })
+    })
+    .collect();
+    files.push(SourceFile {
+        name: "README.md".to_string(),
+        contents: output.readme.clone(),
+        origin: output.origin,
+    });
+    files.extend(
+        output
+            .source_files
+            .iter()
+            .filter(|source_file| !is_reserved_name(&source_file.name))
+            .cloned(),
+    );
+    files
+}
+
+/// The names of the files of the project that project-pilot added itself.
+pub fn tool_files(output: &OutputJson) -> Vec<String> {
+    project_files(output)
+        .into_iter()
+        .filter(|file| file.origin == FileOrigin::Tool)
+        .map(|file| file.name)
+        .collect()
+}
+
+/// A one line summary of the project: the number of files and their extensions.
+pub fn project_summary(output: &OutputJson) -> String {
+    let files = project_files(output);
+    let mut kinds: Vec<String> = files
+        .iter()
+        .map(|file| {
+            Path::new(&file.name)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| file.name.rsplit('/').next().unwrap_or_default().to_string())
+        })
+        .collect();
+    kinds.sort();
+    kinds.dedup();
+    format!("{} file(s) ({})", files.len(), kinds.join(", "))
+}
+
+/// Write `file_contents` to `file_path` and return the number of bytes written.
+/// On failure the error names the file and how many bytes made it to disk.
+pub fn create_file(file_path: &str, file_contents: &str) -> anyhow::Result<usize> {
+    info!("Creating file `{}`", file_path);
+    let start = Instant::now();
+    let file = fs::File::create(file_path)
+        .map_err(|e| anyhow::anyhow!("Failed to create `{}`: {}", file_path, e))?;
+    let mut writer = BufWriter::new(file);
+
+    let mut written = 0;
+    for chunk in file_contents.as_bytes().chunks(WRITE_CHUNK_SIZE) {
+        writer.write_all(chunk).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to write `{}` after {} bytes: {}",
+                file_path,
+                written,
+                e
+            )
+        })?;
+        written += chunk.len();
+    }
+    // Data only reaches the disk once the buffer is flushed.
+    writer.flush().map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to write `{}`, {} bytes may not have been written: {}",
+            file_path,
+            written,
+            e
+        )
+    })?;
+
+    if let Some(writes) = WRITE_PROFILE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()
+    {
+        writes.push(FileWrite {
+            path: file_path.to_string(),
+            bytes: written,
+            duration: start.elapsed(),
+        });
+    }
+    Ok(written)
+}
+
+/// The `.gitignore` of an existing project folder, read before anything is written to it.
+pub struct ProjectGitignore {
+    path: String,
+    gitignore: ignore::gitignore::Gitignore,
+}
+
+impl ProjectGitignore {
+    /// Read the `.gitignore` of `project_path`, `None` when there is none.
+    pub fn read(project_path: &str) -> anyhow::Result<Option<Self>> {
+        let path = format!("{}/.gitignore", project_path);
+        if !Path::new(&path).is_file() {
+            return Ok(None);
+        }
+        let mut builder = ignore::gitignore::GitignoreBuilder::new(project_path);
+        if let Some(e) = builder.add(&path) {
+            anyhow::bail!("Failed to read `{}`: {}", path, e);
+        }
+        let gitignore = builder.build()?;
+        Ok(Some(Self { path, gitignore }))
+    }
+
+    /// Why `name` must be skipped, `None` when it can be written. Names that aren't relative
+    /// paths inside the project can't be matched and are skipped as well.
+    pub fn skip_reason(&self, name: &str) -> Option<String> {
+        let path = Path::new(name);
+        if !path.components().all(|c| {
+            matches!(
+                c,
+                std::path::Component::Normal(_) | std::path::Component::CurDir
+            )
+        }) {
+            return Some("it isn't a relative path inside the project".to_string());
+        }
+        self.gitignore
+            .matched_path_or_any_parents(path, name.ends_with('/'))
+            .is_ignore()
+            .then(|| format!("`{}` ignores it", self.path))
+    }
+
+    /// Drop the source files that must be skipped, warning about each of them.
+    pub fn skip_ignored(&self, source_files: &mut Vec<SourceFile>, warnings: &mut Warnings) {
+        source_files.retain(|source_file| match self.skip_reason(&source_file.name) {
+            Some(reason) => {
+                warnings.warn(format!(
+                    "Skipping source file `{}` because {}",
+                    source_file.name, reason
+                ));
+                false
+            }
+            None => true,
+        });
+    }
+}
+
+/// Write the source files with at most `concurrency` writes at a time. Every file is
+/// attempted, the error lists all the files that failed.
+pub async fn create_source_files(
     source_files_path: &str,
-    source_files: Vec<SourceFile>,
+    source_files: &[SourceFile],
+    options: &WriteOptions,
+    concurrency: usize,
 ) -> anyhow::Result<()> {
-    println!("Creating source files folder `{}`", source_files_path);
+    info!("Creating source files folder `{}`", source_files_path);
 
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
     for source_file in source_files {
-        if source_file.name.to_lowercase().contains("makefile")
-            || source_file.name.to_lowercase().contains("dockerfile")
-            || source_file.name.to_lowercase().contains("readme")
+        if is_reserved_name(&source_file.name) {
+            continue;
+        }
+        let permit = semaphore.clone().acquire_owned().await?;
+        let (source_files_path, source_file, options) = (
+            source_files_path.to_string(),
+            source_file.clone(),
+            options.clone(),
+        );
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            write_source_file(&source_files_path, &source_file, &options)
+        });
+    }
+
+    let mut failures = Vec::new();
+    while let Some(res) = tasks.join_next().await {
+        if let Err(e) = res? {
+            failures.push(e.to_string());
+        }
+    }
+    if !failures.is_empty() {
+        anyhow::bail!(
+            "Failed to write {} source file(s):\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+pub fn create_source_file(
+    source_files_path: &str,
+    source_file: &SourceFile,
+    options: &WriteOptions,
+) -> anyhow::Result<()> {
+    if is_reserved_name(&source_file.name) {
+        return Ok(());
+    }
+    write_source_file(source_files_path, source_file, options)
+}
+
+fn write_source_file(
+    source_files_path: &str,
+    source_file: &SourceFile,
+    options: &WriteOptions,
+) -> anyhow::Result<()> {
+    let written = write_project_file(
+        source_files_path,
+        &source_file.name,
+        &source_file.contents,
+        options,
+    )?;
+    if let Some(written) = written {
+        info!(
+            "Created source file `{}/{}` ({} bytes)",
+            source_files_path, source_file.name, written
+        );
+    }
+
+    Ok(())
+}
+
+/// How files are written into the project folder.
+#[derive(Debug, Default, Clone)]
+pub struct WriteOptions {
+    /// Leave files that already exist untouched.
+    pub skip_existing: bool,
+    /// Copy files that are about to be overwritten to this folder first.
+    pub backup_dir: Option<String>,
+    /// Read each file back after writing it and compare it to what was meant to be written.
+    pub verify: bool,
+}
+
+/// A suffix for temporary paths that is unique across concurrent runs: the process id and a
+/// random number.
+pub fn unique_suffix() -> String {
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    format!("{}-{:08x}", std::process::id(), random as u32)
+}
+
+/// Fail early when files can't be created in `dir`, or in its closest existing ancestor when
+/// it doesn't exist yet, by creating and removing a temporary file.
+pub fn check_writable(dir: &str) -> anyhow::Result<()> {
+    let mut existing = Path::new(dir);
+    while !existing.exists() {
+        existing = match existing.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+    }
+
+    let probe = existing.join(format!(".project-pilot-write-check-{}", unique_suffix()));
+    fs::write(&probe, b"").map_err(|e| {
+        anyhow::anyhow!(
+            "Can't write to `{}` ({}), choose another output location",
+            existing.display(),
+            e
+        )
+    })?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
+
+// The folder containing `file_path`, `name` is the source file the path was built from.
+fn parent_dir<'a>(file_path: &'a str, name: &str) -> anyhow::Result<&'a Path> {
+    let path = Path::new(file_path);
+    if path.file_name().is_none() || name.trim().is_empty() || name.trim_end().ends_with('/') {
+        anyhow::bail!("Source file name `{}` doesn't name a file", name);
+    }
+    path.parent().ok_or_else(|| {
+        anyhow::anyhow!(
+            "Could not determine the folder of source file `{}` (`{}`)",
+            name,
+            file_path
+        )
+    })
+}
+
+/// Write the file `name`, relative to `project_path`, creating its parent folders.
+/// Returns the number of bytes written, or `None` when the file was skipped.
+pub fn write_project_file(
+    project_path: &str,
+    name: &str,
+    contents: &str,
+    options: &WriteOptions,
+) -> anyhow::Result<Option<usize>> {
+    let file_path = format!("{}/{}", project_path, name);
+    let parent = parent_dir(&file_path, name)?;
+    if Path::new(&file_path).is_file() {
+        if options.skip_existing {
+            info!("`{}` exists, skipping", file_path);
+            return Ok(None);
+        }
+        if let Some(backup_dir) = &options.backup_dir {
+            let backup_path = format!("{}/{}", backup_dir, name);
+            fs::create_dir_all(parent_dir(&backup_path, name)?)?;
+            fs::copy(&file_path, &backup_path).map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to back up `{}` to `{}`: {}",
+                    file_path,
+                    backup_path,
+                    e
+                )
+            })?;
+            info!("Backed up `{}` to `{}`", file_path, backup_path);
+        }
+    }
+
+    fs::create_dir_all(parent)?;
+
+    let written = create_file(&file_path, contents)?;
+    if options.verify {
+        verify_file(&file_path, contents)?;
+    }
+    Ok(Some(written))
+}
+
+fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Fail when the file at `file_path` doesn't contain exactly `contents`.
+fn verify_file(file_path: &str, contents: &str) -> anyhow::Result<()> {
+    let on_disk = fs::read(file_path)
+        .map_err(|e| anyhow::anyhow!("Failed to read back `{}`: {}", file_path, e))?;
+    if content_hash(&on_disk) != content_hash(contents.as_bytes()) {
+        anyhow::bail!(
+            "`{}` doesn't match what was written: {} bytes on disk, {} bytes expected",
+            file_path,
+            on_disk.len(),
+            contents.len()
+        );
+    }
+    Ok(())
+}
+
+/// The conventional linter config file for `language`, if the language has one.
+pub fn lint_config_file(language: &str) -> Option<&'static str> {
+    match language.to_lowercase().as_str() {
+        "javascript" | "typescript" => Some(".eslintrc.json"),
+        "python" => Some("ruff.toml"),
+        "rust" => Some("clippy.toml"),
+        "go" => Some(".golangci.yml"),
+        "ruby" => Some(".rubocop.yml"),
+        "php" => Some("phpcs.xml"),
+        "bash" | "sh" => Some(".shellcheckrc"),
+        _ => None,
+    }
+}
+
+/// A `.gitignore` following the github/gitignore conventions of `language`, for the common
+/// languages.
+pub fn gitignore_template(language: &str) -> Option<&'static str> {
+    match language.to_lowercase().as_str() {
+        "rust" => Some(include_str!("../gitignore/Rust.gitignore")),
+        "python" => Some(include_str!("../gitignore/Python.gitignore")),
+        "javascript" | "typescript" | "node" => Some(include_str!("../gitignore/Node.gitignore")),
+        "go" => Some(include_str!("../gitignore/Go.gitignore")),
+        "java" | "kotlin" => Some(include_str!("../gitignore/Java.gitignore")),
+        "c" => Some(include_str!("../gitignore/C.gitignore")),
+        "c++" | "cpp" => Some(include_str!("../gitignore/C++.gitignore")),
+        "ruby" => Some(include_str!("../gitignore/Ruby.gitignore")),
+        _ => None,
+    }
+}
+
+/// Make sure the files at `expected` paths are part of the source files. A file with the same
+/// file name in another folder is moved to the expected path, missing files produce a warning.
+pub fn expect_files(source_files: &mut [SourceFile], expected: &[&str], warnings: &mut Warnings) {
+    for path in expected {
+        if source_files.iter().any(|f| f.name == *path) {
+            continue;
+        }
+
+        let file_name = Path::new(path).file_name();
+        match source_files
+            .iter_mut()
+            .find(|f| Path::new(&f.name).file_name() == file_name)
         {
-            println!(
-                "Skipping source file `{}` because it was already created",
+            Some(source_file) => {
+                info!("Moving `{}` to `{}`", source_file.name, path);
+                source_file.name = path.to_string();
+            }
+            None => warnings.warn(format!("The model didn't generate `{}`", path)),
+        }
+    }
+}
+
+/// The path of `expected` that `expect_files` moves the file `name` to, for files handled before
+/// the others are known, like while streaming.
+pub fn expected_path<'a>(name: &str, expected: &[&'a str]) -> Option<&'a str> {
+    if expected.contains(&name) {
+        return None;
+    }
+    let file_name = Path::new(name).file_name();
+    expected
+        .iter()
+        .copied()
+        .find(|path| Path::new(path).file_name() == file_name)
+}
+
+/// Reflow the paragraphs and list items of a markdown document to `width` columns.
+/// Code blocks, headings, tables, quotes and html are kept as they are.
+pub fn wrap_markdown(markdown: &str, width: usize) -> String {
+    let mut out: Vec<String> = Vec::new();
+    // The text of the paragraph or list item being collected and its list marker.
+    let mut block: Option<(String, String)> = None;
+    let mut in_fence = false;
+
+    let flush = |block: &mut Option<(String, String)>, out: &mut Vec<String>| {
+        if let Some((marker, text)) = block.take() {
+            let indent = " ".repeat(marker.len());
+            let options = textwrap::Options::new(width)
+                .initial_indent(&marker)
+                .subsequent_indent(&indent);
+            out.extend(textwrap::wrap(&text, options).into_iter().map(String::from));
+        }
+    };
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            flush(&mut block, &mut out);
+            in_fence = !in_fence;
+            out.push(line.to_string());
+            continue;
+        }
+        if in_fence {
+            out.push(line.to_string());
+            continue;
+        }
+
+        let verbatim = trimmed.is_empty()
+            || line.starts_with("    ")
+            || line.starts_with('\t')
+            || ["#", "|", ">", "<"].iter().any(|p| trimmed.starts_with(p));
+        if verbatim {
+            flush(&mut block, &mut out);
+            out.push(line.to_string());
+        } else if let Some(marker) = list_marker(line) {
+            flush(&mut block, &mut out);
+            block = Some((marker.to_string(), line[marker.len()..].trim().to_string()));
+        } else if let Some((_, text)) = block.as_mut() {
+            text.push(' ');
+            text.push_str(line.trim());
+        } else {
+            block = Some((String::new(), line.trim().to_string()));
+        }
+    }
+    flush(&mut block, &mut out);
+
+    let mut wrapped = out.join("\n");
+    if markdown.ends_with('\n') {
+        wrapped.push('\n');
+    }
+    wrapped
+}
+
+// The marker of a list item including its indentation and trailing space, e.g. `  - ` or `1. `.
+fn list_marker(line: &str) -> Option<&str> {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let marker_len = if ["- ", "* ", "+ "].iter().any(|m| rest.starts_with(m)) {
+        2
+    } else {
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits == 0 || !rest[digits..].starts_with(". ") {
+            return None;
+        }
+        digits + 2
+    };
+    Some(&line[..indent + marker_len])
+}
+
+/// Fail when several source files have the same name, `what` telling what renamed them.
+pub fn check_unique_names(source_files: &[SourceFile], what: &str) -> anyhow::Result<()> {
+    for (i, source_file) in source_files.iter().enumerate() {
+        if source_files[..i].iter().any(|f| f.name == source_file.name) {
+            anyhow::bail!(
+                "{} several source files end up as `{}`",
+                what,
                 source_file.name
             );
+        }
+    }
+    Ok(())
+}
+
+/// Rename the source files matching the `(old, new)` prefix rules, the longest matching `old`
+/// prefix wins. Fails when two source files end up with the same name.
+pub fn rename_source_files(
+    source_files: &mut [SourceFile],
+    rules: &[(String, String)],
+) -> anyhow::Result<()> {
+    for source_file in source_files.iter_mut() {
+        let Some((old, new)) = rules
+            .iter()
+            .filter(|(old, _)| source_file.name.starts_with(old.as_str()))
+            .max_by_key(|(old, _)| old.len())
+        else {
+            continue;
+        };
+
+        let renamed = format!("{}{}", new, &source_file.name[old.len()..]);
+        info!("Renamed `{}` to `{}`", source_file.name, renamed);
+        source_file.name = renamed;
+    }
+
+    check_unique_names(source_files, "The rename rules make")
+}
+
+/// Transliterate the non-ASCII characters in the names of the source files to ASCII.
+/// Fails when two source files end up with the same name.
+pub fn ascii_file_names(source_files: &mut [SourceFile]) -> anyhow::Result<()> {
+    for source_file in source_files.iter_mut() {
+        if source_file.name.is_ascii() {
             continue;
         }
+        // Transliterate character by character, the transliterations of some scripts end with
+        // a space to separate words.
+        let ascii: String = source_file
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii() {
+                    c.to_string()
+                } else {
+                    deunicode::deunicode_char(c)
+                        .unwrap_or("_")
+                        .trim()
+                        .to_string()
+                }
+            })
+            .collect();
+        info!("Renamed `{}` to `{}`", source_file.name, ascii);
+        source_file.name = ascii;
+    }
 
-        let source_file_path = format!("{}/{}", source_files_path, source_file.name);
-        let parent = Path::new(&source_file_path).parent().unwrap();
-        fs::create_dir_all(parent)?;
+    check_unique_names(source_files, "Transliterating the file names makes")
+}
 
-        create_file(&source_file_path, &source_file.contents)?;
-        println!("Created source file `{}`", source_file_path);
+/// `name` without `.` segments, repeated or leading separators and the `..` segments that
+/// stay within the project root. Names of folders, ending with a separator, are kept as is.
+pub fn normalize_path(name: &str) -> anyhow::Result<String> {
+    if name.ends_with('/') {
+        return Ok(name.to_string());
     }
+    let mut segments = Vec::new();
+    for segment in name.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    anyhow::bail!("Source file `{}` is outside of the project folder", name);
+                }
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Ok(segments.join("/"))
+}
 
-    Ok(())
+/// Normalize the names of the source files with `normalize_path`.
+/// Fails when two source files end up with the same name.
+pub fn normalize_file_names(source_files: &mut [SourceFile]) -> anyhow::Result<()> {
+    for source_file in source_files.iter_mut() {
+        let normalized = normalize_path(&source_file.name)?;
+        if normalized != source_file.name {
+            info!("Normalized `{}` to `{}`", source_file.name, normalized);
+            source_file.name = normalized;
+        }
+    }
+
+    check_unique_names(source_files, "Normalizing the file names makes")
+}
+
+/// Check that JSON and YAML source files parse and reformat the valid ones, JSON indented with
+/// `json_indent` spaces. YAML files with comments or multiple documents are only checked, since
+/// reformatting them would lose information.
+pub fn validate_configs(
+    source_files: &mut [SourceFile],
+    json_indent: usize,
+    warnings: &mut Warnings,
+) {
+    for source_file in source_files {
+        let extension = Path::new(&source_file.name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "json" => match serde_json::from_str::<serde_json::Value>(&source_file.contents) {
+                Ok(value) => {
+                    if let Ok(formatted) = to_json(&value, json_indent) {
+                        source_file.contents = formatted + "\n";
+                    }
+                }
+                Err(e) => warnings.warn(format!("`{}` is not valid JSON: {}", source_file.name, e)),
+            },
+            "yaml" | "yml" => {
+                let mut documents = Vec::new();
+                for document in serde_yaml::Deserializer::from_str(&source_file.contents) {
+                    match serde_yaml::Value::deserialize(document) {
+                        Ok(value) => documents.push(value),
+                        Err(e) => {
+                            warnings
+                                .warn(format!("`{}` is not valid YAML: {}", source_file.name, e));
+                            documents.clear();
+                            break;
+                        }
+                    }
+                }
+                if documents.len() == 1 && !source_file.contents.contains('#') {
+                    if let Ok(formatted) = serde_yaml::to_string(&documents[0]) {
+                        source_file.contents = formatted;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentStyle {
+    Slash,
+    Hash,
+    Semicolon,
+    Html,
+}
+
+impl CommentStyle {
+    pub fn comment(&self, lines: &[String]) -> String {
+        match self {
+            CommentStyle::Slash => lines.iter().map(|l| format!("// {}\n", l)).collect(),
+            CommentStyle::Hash => lines.iter().map(|l| format!("# {}\n", l)).collect(),
+            CommentStyle::Semicolon => lines.iter().map(|l| format!("; {}\n", l)).collect(),
+            CommentStyle::Html => format!(
+                "<!--\n{}-->\n",
+                lines
+                    .iter()
+                    .map(|l| format!("  {}\n", l))
+                    .collect::<String>()
+            ),
+        }
+    }
+}
+
+/// Detect the comment style of a source file from its extension (or well known file name).
+/// Returns `None` for formats without comments, like JSON.
+pub fn comment_style(file_name: &str) -> Option<CommentStyle> {
+    let path = Path::new(file_name);
+    let base = path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if base.contains("dockerfile")
+        || base.contains("makefile")
+        || base == ".gitignore"
+        || base == ".dockerignore"
+    {
+        return Some(CommentStyle::Hash);
+    }
+
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "rs" | "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" | "go" | "java" | "kt" | "kts"
+        | "scala" | "swift" | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "dart" | "php" | "css"
+        | "scss" | "groovy" | "zig" => Some(CommentStyle::Slash),
+        "py" | "rb" | "sh" | "bash" | "zsh" | "pl" | "r" | "toml" | "yaml" | "yml" | "ex"
+        | "exs" | "jl" | "nim" | "cr" | "ps1" | "conf" | "ini" | "cfg" => Some(CommentStyle::Hash),
+        "clj" | "cljs" | "lisp" | "el" | "scm" | "asm" => Some(CommentStyle::Semicolon),
+        "html" | "htm" | "xml" | "md" | "vue" | "svelte" | "svg" => Some(CommentStyle::Html),
+        _ => None,
+    }
+}
+
+/// Prepend a comment to each source file recording the description and model that generated it,
+/// in `comment_style` when given instead of the style detected for each file. Files with an
+/// unknown comment style are left untouched.
+pub fn stamp_source_files(
+    source_files: &mut [SourceFile],
+    description: &str,
+    model: &str,
+    language_map: &LanguageMap,
+    comment_style: Option<CommentStyle>,
+) {
+    let mut lines = vec![format!(
+        "Generated by project-pilot using model `{}`.",
+        model
+    )];
+    lines.push("Description:".to_string());
+    lines.extend(description.lines().map(|l| format!("  {}", l.trim())));
+
+    for source_file in source_files {
+        if !prepend_comment(source_file, &lines, language_map, comment_style) {
+            info!("Not stamping `{}`, unknown comment style", source_file.name);
+        }
+    }
+}
+
+/// Prepend `lines` as a comment to the source file, in `style` or else the style detected for
+/// the file. Returns false when the file has no known comment syntax, like JSON, and it was left
+/// untouched.
+pub fn prepend_comment(
+    source_file: &mut SourceFile,
+    lines: &[String],
+    language_map: &LanguageMap,
+    style: Option<CommentStyle>,
+) -> bool {
+    let Some(detected) = language_map.comment_style(&source_file.name) else {
+        return false;
+    };
+    let style = style.unwrap_or(detected);
+
+    let header = style.comment(lines);
+    // Keep shebangs and xml declarations on the first line.
+    source_file.contents =
+        if source_file.contents.starts_with("#!") || source_file.contents.starts_with("<?xml") {
+            match source_file.contents.split_once('\n') {
+                Some((first, rest)) => format!("{}\n{}{}", first, header, rest),
+                None => format!("{}\n{}", source_file.contents, header),
+            }
+        } else {
+            format!("{}{}", header, source_file.contents)
+        };
+    true
+}
+
+/// Directory of a monorepo where generated projects are placed.
+pub const MONOREPO_PACKAGES_DIR: &str = "packages";
+
+/// Whether the workspace member pattern `pattern` covers the `member` path.
+fn covers_member(pattern: &str, member: &str) -> bool {
+    let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+    if pattern == member {
+        return true;
+    }
+    let parent = member.rsplit_once('/').map_or("", |(parent, _)| parent);
+    matches!(pattern.strip_suffix("/*").or(pattern.strip_suffix("/**")), Some(p) if p == parent)
+}
+
+/// The `members` of the `[workspace]` section of a Cargo.toml, `None` when it doesn't define a
+/// workspace or can't be parsed.
+fn cargo_workspace_members(manifest: &str) -> Option<Vec<String>> {
+    let manifest: toml::Table = manifest.parse().ok()?;
+    let workspace = manifest.get("workspace")?.as_table()?;
+    Some(
+        workspace
+            .get("members")
+            .and_then(|members| members.as_array())
+            .map(|members| {
+                members
+                    .iter()
+                    .filter_map(|member| member.as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    )
+}
+
+/// Look for the workspace files of the monorepo at `root` and describe those that have to be
+/// edited before `member` is part of the workspace.
+pub fn workspace_edits(root: &str, member: &str) -> Vec<String> {
+    let read = |file: &str| fs::read_to_string(Path::new(root).join(file)).ok();
+    let strings = |value: Option<&serde_json::Value>| -> Vec<String> {
+        value
+            .and_then(|value| value.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut workspaces = Vec::new();
+    if let Some(members) = read("Cargo.toml").and_then(|c| cargo_workspace_members(&c)) {
+        workspaces.push(("Cargo.toml", "members", members));
+    }
+    if let Some(package) = read("package.json")
+        .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+        .filter(|package| package.get("workspaces").is_some())
+    {
+        // `workspaces` is either a list or an object with a `packages` list.
+        let workspaces_value = &package["workspaces"];
+        let members = match workspaces_value.get("packages") {
+            Some(packages) => strings(Some(packages)),
+            None => strings(Some(workspaces_value)),
+        };
+        workspaces.push(("package.json", "workspaces", members));
+    }
+    if let Some(workspace) =
+        read("pnpm-workspace.yaml").and_then(|c| serde_yaml::from_str::<serde_json::Value>(&c).ok())
+    {
+        workspaces.push((
+            "pnpm-workspace.yaml",
+            "packages",
+            strings(workspace.get("packages")),
+        ));
+    }
+    if let Some(go_work) = read("go.work") {
+        let members = go_work
+            .lines()
+            .map(|line| line.trim().trim_start_matches("use").trim())
+            .filter(|line| line.starts_with('.'))
+            .map(String::from)
+            .collect();
+        workspaces.push(("go.work", "use", members));
+    }
+
+    workspaces
+        .into_iter()
+        .filter(|(_, _, members)| !members.iter().any(|pattern| covers_member(pattern, member)))
+        .map(|(file, key, _)| {
+            format!(
+                "`{}` defines a workspace that doesn't include `{}`, add it to `{}`",
+                file, member, key
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Strings made of the characters the decoders look for, more likely to reach the edge cases
+    // than arbitrary unicode.
+    const JSONISH: &str = "[{}\\[\\]\":,`a-z0-9\\\\ \n]{0,200}";
+    const ENV_REFS: &str = "(\\$\\{|\\$|[A-Z_]|\\}|é|.){0,60}";
+
+    const ALL_FIELDS: [OutputField; 4] = [
+        OutputField::Dockerfile,
+        OutputField::Makefile,
+        OutputField::Readme,
+        OutputField::SourceFiles,
+    ];
+
+    const NO_FILE_NAMES: [&str; 5] = ["/", "", "..", "dir/", "  "];
+
+    #[test]
+    fn parent_dir_rejects_names_without_a_file() {
+        for name in NO_FILE_NAMES {
+            let file_path = format!("project/{}", name);
+            assert!(parent_dir(&file_path, name).is_err(), "`{}`", name);
+        }
+        assert_eq!(
+            parent_dir("project/dir/main.rs", "dir/main.rs").unwrap(),
+            Path::new("project/dir")
+        );
+    }
+
+    #[test]
+    fn write_project_file_rejects_names_without_a_file() {
+        let project_path = std::env::temp_dir().join(format!("project-pilot-{}", unique_suffix()));
+        let project_path = project_path.to_str().unwrap();
+        for name in NO_FILE_NAMES {
+            let res = write_project_file(project_path, name, "contents", &WriteOptions::default());
+            assert!(res.is_err(), "`{}`", name);
+        }
+        assert!(!Path::new(project_path).exists());
+
+        let written = write_project_file(
+            project_path,
+            "dir/main.rs",
+            "contents",
+            &WriteOptions::default(),
+        );
+        assert_eq!(written.unwrap(), Some(8));
+        fs::remove_dir_all(project_path).unwrap();
+    }
+
+    #[test]
+    fn max_output_tokens_prefers_specific_models() {
+        assert_eq!(max_output_tokens("gpt-4"), Some(8192));
+        assert_eq!(max_output_tokens("gpt-4-0613"), Some(8192));
+        assert_eq!(max_output_tokens("gpt-4-0125-preview"), Some(4096));
+        assert_eq!(max_output_tokens("gpt-4-1106-preview"), Some(4096));
+        assert_eq!(max_output_tokens("gpt-4-turbo-preview"), Some(4096));
+    }
+
+    #[test]
+    fn stamp_skips_files_without_comments_with_a_forced_style() {
+        let mut source_files = vec![
+            SourceFile {
+                name: "package.json".to_string(),
+                contents: "{}\n".to_string(),
+                origin: FileOrigin::Model,
+            },
+            SourceFile {
+                name: "main.py".to_string(),
+                contents: "print()\n".to_string(),
+                origin: FileOrigin::Model,
+            },
+        ];
+        stamp_source_files(
+            &mut source_files,
+            "A demo",
+            "gpt-4",
+            &LanguageMap::default(),
+            Some(CommentStyle::Slash),
+        );
+        assert_eq!(source_files[0].contents, "{}\n");
+        assert!(source_files[1]
+            .contents
+            .starts_with("// Generated by project-pilot"));
+    }
+
+    proptest! {
+        #[test]
+        fn decode_output_never_panics(content in any::<String>(), docker: bool) {
+            let _ = decode_output(&content, "", docker, &[]);
+            let _ = decode_output(&content, "", docker, &ALL_FIELDS);
+        }
+
+        #[test]
+        fn decode_output_never_panics_on_jsonish(content in JSONISH, docker: bool) {
+            let _ = decode_output(&content, "", docker, &ALL_FIELDS);
+            let _ = decode_output(&content, "/project", docker, &[]);
+        }
+
+        #[test]
+        fn merge_continuation_never_panics(
+            previous in any::<String>(),
+            next in any::<String>(),
+            jsonish_previous in JSONISH,
+            jsonish_next in JSONISH,
+        ) {
+            let _ = merge_continuation(&previous, &next);
+            let _ = merge_continuation(&jsonish_previous, &jsonish_next);
+        }
+
+        #[test]
+        fn is_refusal_never_panics(content in any::<String>()) {
+            let _ = is_refusal(&content);
+        }
+
+        #[test]
+        fn expand_env_never_panics(text in any::<String>(), refs in ENV_REFS) {
+            let _ = expand_env(&text);
+            let _ = expand_env(&refs);
+        }
+    }
 }
@@ -0,0 +1,262 @@
+use async_openai::Client;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::utils::{self, SourceFile};
+use crate::{build_request, create_with_continuations, Args};
+
+// Don't send huge projects, the prompt would exceed the context window anyway.
+const MAX_PROJECT_BYTES: usize = 200 * 1024;
+// Lines of output shown for each failed build.
+const BUILD_OUTPUT_LINES: usize = 20;
+const SKIPPED_DIRS: [&str; 6] = [
+    ".git",
+    ".project-pilot",
+    "target",
+    "node_modules",
+    "dist",
+    "vendor",
+];
+
+/// The verdict of the model on whether a project satisfies its spec.
+#[derive(Debug, Deserialize)]
+pub struct Validation {
+    pub pass: bool,
+    #[serde(default)]
+    pub gaps: Vec<String>,
+}
+
+/// Ask the model whether the project in `dir` satisfies `description`, failing when it doesn't.
+pub async fn validate_project(
+    args: &Args,
+    client: &Client,
+    dir: &str,
+    description: &str,
+) -> anyhow::Result<()> {
+    let files = read_project(dir)?;
+    if files.is_empty() {
+        anyhow::bail!("No files found in `{}`", dir);
+    }
+    println!(
+        "Validating {} file(s) from `{}` against the spec... 🤖",
+        files.len(),
+        dir
+    );
+
+    let prompt = utils::generate_validation_prompt(description, &files);
+    let req = build_request(args, utils::VALIDATION_SYSTEM_PROMPT, &prompt)?;
+    let completion = create_with_continuations(client, req, args.max_continuations).await?;
+    let validation: Validation = serde_json::from_str(&completion.content).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to decode the validation ({}), the response was:\n{}",
+            e,
+            completion.content
+        )
+    })?;
+
+    if validation.pass {
+        println!("PASS ✅ The project satisfies the spec.");
+    } else {
+        println!("FAIL ❌ The project doesn't satisfy the spec.");
+    }
+    for gap in &validation.gaps {
+        println!("- {}", gap);
+    }
+
+    if !validation.pass {
+        anyhow::bail!("`{}` doesn't satisfy the spec", dir);
+    }
+    Ok(())
+}
+
+/// The outcome of `make build` in a project.
+struct Build {
+    name: String,
+    duration: Duration,
+    error: Option<String>,
+    output: String,
+}
+
+async fn make_build(name: String, dir: PathBuf) -> Build {
+    let start = Instant::now();
+    let res = tokio::process::Command::new("make")
+        .arg("build")
+        .current_dir(&dir)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await;
+    let (error, output) = match res {
+        Ok(output) => {
+            let text = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            let error = (!output.status.success())
+                .then(|| format!("`make build` failed with {}", output.status));
+            (error, text)
+        }
+        Err(e) => (
+            Some(format!("Failed to run `make build`: {}", e)),
+            String::new(),
+        ),
+    };
+    Build {
+        name,
+        duration: start.elapsed(),
+        error,
+        output,
+    }
+}
+
+/// Run `make build` in every subfolder of `dir` with a Makefile, at most `concurrency` at a
+/// time, and print which projects pass. Fails when any build fails.
+pub async fn validate_all(dir: &str, concurrency: usize) -> anyhow::Result<()> {
+    let mut projects: Vec<(String, PathBuf)> = fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to read `{}`: {}", dir, e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("Makefile").is_file())
+        .map(|path| {
+            (
+                path.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                path,
+            )
+        })
+        .collect();
+    if projects.is_empty() {
+        anyhow::bail!("No projects with a Makefile found in `{}`", dir);
+    }
+    projects.sort();
+    println!(
+        "Building {} project(s) from `{}`, {} at a time... 🔨",
+        projects.len(),
+        dir,
+        concurrency
+    );
+
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for (name, path) in projects {
+        let permit = semaphore.clone().acquire_owned().await?;
+        tasks.spawn(async move {
+            let _permit = permit;
+            make_build(name, path).await
+        });
+    }
+    let mut builds = Vec::new();
+    while let Some(build) = tasks.join_next().await {
+        builds.push(build?);
+    }
+    builds.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let width = builds
+        .iter()
+        .map(|build| build.name.len())
+        .max()
+        .unwrap_or(0);
+    for build in &builds {
+        let status = if build.error.is_none() {
+            "PASS ✅"
+        } else {
+            "FAIL ❌"
+        };
+        println!(
+            "{}  {:<width$}  {:>7.1} s",
+            status,
+            build.name,
+            build.duration.as_secs_f64(),
+            width = width
+        );
+    }
+    let failed: Vec<&Build> = builds
+        .iter()
+        .filter(|build| build.error.is_some())
+        .collect();
+    for build in &failed {
+        println!(
+            "\n{}: {}",
+            build.name,
+            build.error.as_deref().unwrap_or_default()
+        );
+        let lines: Vec<&str> = build.output.lines().collect();
+        for line in &lines[lines.len().saturating_sub(BUILD_OUTPUT_LINES)..] {
+            println!("  {}", line);
+        }
+    }
+
+    let passed = builds.len() - failed.len();
+    println!(
+        "\n{} of {} project(s) built successfully ({:.0}%)",
+        passed,
+        builds.len(),
+        passed as f64 * 100.0 / builds.len() as f64
+    );
+    if !failed.is_empty() {
+        anyhow::bail!("{} project(s) failed to build", failed.len());
+    }
+    Ok(())
+}
+
+/// Read the text files of a project, sorted by path and relative to `dir`.
+pub fn read_project(dir: &str) -> anyhow::Result<Vec<SourceFile>> {
+    let mut files = Vec::new();
+    let mut total = 0;
+    read_dir(Path::new(dir), Path::new(dir), &mut files, &mut total)?;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+fn read_dir(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<SourceFile>,
+    total: &mut usize,
+) -> anyhow::Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to read `{}`: {}", dir.display(), e))?
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            let name = entry.file_name();
+            if !SKIPPED_DIRS.iter().any(|skipped| name == *skipped) {
+                read_dir(root, &path, files, total)?;
+            }
+            continue;
+        }
+
+        // Binary files can't be sent to the model.
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        if *total + contents.len() > MAX_PROJECT_BYTES {
+            println!(
+                "Skipping `{}`, the project is too large to send",
+                path.display()
+            );
+            continue;
+        }
+        *total += contents.len();
+        files.push(SourceFile {
+            name: path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .display()
+                .to_string(),
+            contents,
+            origin: utils::FileOrigin::Model,
+        });
+    }
+    Ok(())
+}
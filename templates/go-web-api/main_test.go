@@ -0,0 +1,15 @@
+package main
+
+import (
+	"net/http"
+	"net/http/httptest"
+	"testing"
+)
+
+func TestHealth(t *testing.T) {
+	res := httptest.NewRecorder()
+	newRouter().ServeHTTP(res, httptest.NewRequest("GET", "/health", nil))
+	if res.Code != http.StatusOK {
+		t.Fatalf("expected 200, got %d", res.Code)
+	}
+}
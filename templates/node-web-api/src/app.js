@@ -0,0 +1,20 @@
+const http = require("node:http");
+
+const routes = {
+  "GET /health": () => ({ status: 200, body: { status: "ok" } }),
+};
+
+function handle(method, url) {
+  const route = routes[`${method} ${url.split("?")[0]}`];
+  return route ? route() : { status: 404, body: { error: "not found" } };
+}
+
+function createServer() {
+  return http.createServer((req, res) => {
+    const { status, body } = handle(req.method, req.url);
+    res.writeHead(status, { "Content-Type": "application/json" });
+    res.end(JSON.stringify(body));
+  });
+}
+
+module.exports = { createServer, handle };
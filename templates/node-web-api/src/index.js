@@ -0,0 +1,8 @@
+const { createServer } = require("./app");
+
+const port = Number(process.env.PORT) || 3000;
+const server = createServer().listen(port, () => {
+  console.log(`{package} listening on port ${port}`);
+});
+
+process.on("SIGTERM", () => server.close());
@@ -0,0 +1,11 @@
+const test = require("node:test");
+const assert = require("node:assert");
+const { handle } = require("../src/app");
+
+test("GET /health returns 200", () => {
+  assert.strictEqual(handle("GET", "/health").status, 200);
+});
+
+test("unknown routes return 404", () => {
+  assert.strictEqual(handle("GET", "/missing").status, 404);
+});
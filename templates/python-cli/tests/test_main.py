@@ -0,0 +1,15 @@
+import unittest
+
+from {package}.__main__ import greeting
+
+
+class GreetingTest(unittest.TestCase):
+    def test_greets_the_world_by_default(self):
+        self.assertEqual(greeting(), "Hello, world!")
+
+    def test_greets_by_name(self):
+        self.assertEqual(greeting("Guido"), "Hello, Guido!")
+
+
+if __name__ == "__main__":
+    unittest.main()
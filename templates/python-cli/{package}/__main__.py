@@ -0,0 +1,18 @@
+import argparse
+import sys
+
+
+def greeting(name=None):
+    return f"Hello, {name or 'world'}!"
+
+
+def main(argv=None):
+    parser = argparse.ArgumentParser(prog="{package}")
+    parser.add_argument("name", nargs="?", help="who to greet")
+    args = parser.parse_args(argv)
+    print(greeting(args.name))
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
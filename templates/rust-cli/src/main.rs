@@ -0,0 +1,39 @@
+use std::env;
+use std::process::ExitCode;
+
+fn greeting(name: Option<&str>) -> String {
+    format!("Hello, {}!", name.unwrap_or("world"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("-h" | "--help") => {
+            println!("Usage: {package} [NAME]");
+            ExitCode::SUCCESS
+        }
+        Some(arg) if arg.starts_with('-') => {
+            eprintln!("Unknown option `{}`, see --help", arg);
+            ExitCode::FAILURE
+        }
+        name => {
+            println!("{}", greeting(name));
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greets_the_world_by_default() {
+        assert_eq!(greeting(None), "Hello, world!");
+    }
+
+    #[test]
+    fn greets_by_name() {
+        assert_eq!(greeting(Some("Ferris")), "Hello, Ferris!");
+    }
+}